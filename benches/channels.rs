@@ -0,0 +1,97 @@
+use std::{
+    hint::black_box,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use atomics::{
+    cpu::{self, Placement},
+    mpmc,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const MESSAGES: u64 = 10_000;
+const CAPACITY: usize = 64;
+const BATCH: usize = 32;
+
+fn spawn_on<F>(core: Option<usize>, f: F) -> JoinHandle<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    match core {
+        Some(core) => cpu::spawn_pinned(core, f),
+        None => thread::spawn(f),
+    }
+}
+
+//  one producer and one consumer passing `MESSAGES` messages per iteration through a fresh
+//  channel, optionally pinned to the given pair of cores
+fn pipeline(iters: u64, batched: bool, cores: Option<(usize, usize)>) -> Duration {
+    let (sender, receiver) = mpmc::bounded(CAPACITY);
+    let start = Instant::now();
+    let producer = spawn_on(cores.map(|(core, _)| core), move || {
+        if batched {
+            let values: Vec<u64> = (0..MESSAGES).collect();
+            for _ in 0..iters {
+                for chunk in values.chunks(BATCH) {
+                    sender.send_all(chunk.iter().copied()).unwrap();
+                }
+            }
+        } else {
+            for _ in 0..iters {
+                for value in 0..MESSAGES {
+                    sender.send(value).unwrap();
+                }
+            }
+        }
+    });
+    let consumer = spawn_on(cores.map(|(_, core)| core), move || {
+        if batched {
+            let mut buf = Vec::with_capacity(BATCH);
+            while receiver.recv_batch(&mut buf, BATCH).is_ok() {
+                black_box(&buf);
+                buf.clear();
+            }
+        } else {
+            while let Ok(value) = receiver.recv() {
+                black_box(value);
+            }
+        }
+    });
+    producer.join().unwrap();
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+fn bench_mpmc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mpmc_pipeline");
+    group.throughput(Throughput::Elements(MESSAGES));
+    let placements = [
+        ("unpinned", None),
+        ("same_core", Some(Placement::SameCore)),
+        ("different_cores", Some(Placement::DifferentCores)),
+        ("different_nodes", Some(Placement::DifferentNodes)),
+    ];
+    for (placement, wanted) in placements {
+        //  placements this machine cannot provide are skipped rather than run unpinned
+        let cores = match wanted {
+            Some(wanted) => match cpu::pair_cores(wanted) {
+                Some(cores) => Some(cores),
+                None => continue,
+            },
+            None => None,
+        };
+        for batched in [false, true] {
+            let name = if batched { "batched" } else { "single" };
+            group.bench_with_input(
+                BenchmarkId::new(name, placement),
+                &batched,
+                |b, &batched| b.iter_custom(|iters| pipeline(iters, batched, cores)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_mpmc);
+criterion_main!(benches);
@@ -0,0 +1,143 @@
+use std::{
+    hint::black_box,
+    sync, thread,
+    time::{Duration, Instant},
+};
+
+use atomics::mutex::{AdaptiveMutex, McsLock, Mutex, ParkingMutex, SpinLock, TicketLock};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+//  the one operation every lock under test has in common, so each benchmark is written once
+trait BenchLock: Sync {
+    const NAME: &'static str;
+    fn new(value: u64) -> Self;
+    fn with(&self, f: impl FnOnce(&mut u64));
+}
+
+macro_rules! bench_lock {
+    ($lock:ty, $name:literal, |$this:ident| $guard:expr) => {
+        impl BenchLock for $lock {
+            const NAME: &'static str = $name;
+            fn new(value: u64) -> Self {
+                <$lock>::new(value)
+            }
+            fn with(&self, f: impl FnOnce(&mut u64)) {
+                let $this = self;
+                let mut guard = $guard;
+                f(&mut guard);
+            }
+        }
+    };
+}
+
+bench_lock!(SpinLock<u64>, "spin_lock", |lock| lock.lock().unwrap());
+bench_lock!(TicketLock<u64>, "ticket_lock", |lock| lock.lock());
+bench_lock!(McsLock<u64>, "mcs_lock", |lock| lock.lock());
+bench_lock!(Mutex<u64>, "mutex", |lock| lock.lock());
+bench_lock!(AdaptiveMutex<u64>, "adaptive_mutex", |lock| lock.lock());
+bench_lock!(ParkingMutex<u64>, "parking_mutex", |lock| lock.lock());
+bench_lock!(sync::Mutex<u64>, "std_mutex", |lock| lock.lock().unwrap());
+
+const OPS_PER_THREAD: u64 = 1_000;
+const THREADS: [usize; 5] = [1, 2, 4, 8, 16];
+
+fn contended<L: BenchLock>(lock: &L, threads: usize, iters: u64) -> Duration {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..iters * OPS_PER_THREAD {
+                    lock.with(|value| *value += 1);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn uncontended<L: BenchLock>(c: &mut Criterion) {
+    let lock = L::new(0);
+    c.benchmark_group("lock_uncontended")
+        .bench_function(L::NAME, |b| {
+            b.iter(|| lock.with(|value| *black_box(value) += 1))
+        });
+}
+
+fn throughput<L: BenchLock>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("lock_contended/{}", L::NAME));
+    for threads in THREADS {
+        group.throughput(Throughput::Elements(threads as u64 * OPS_PER_THREAD));
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &n| {
+            let lock = L::new(0);
+            b.iter_custom(|iters| contended(&lock, n, iters));
+        });
+    }
+    group.finish();
+}
+
+fn bench_locks(c: &mut Criterion) {
+    uncontended::<SpinLock<u64>>(c);
+    uncontended::<TicketLock<u64>>(c);
+    uncontended::<McsLock<u64>>(c);
+    uncontended::<Mutex<u64>>(c);
+    uncontended::<AdaptiveMutex<u64>>(c);
+    uncontended::<ParkingMutex<u64>>(c);
+    uncontended::<sync::Mutex<u64>>(c);
+
+    throughput::<SpinLock<u64>>(c);
+    throughput::<TicketLock<u64>>(c);
+    throughput::<McsLock<u64>>(c);
+    throughput::<Mutex<u64>>(c);
+    throughput::<AdaptiveMutex<u64>>(c);
+    throughput::<ParkingMutex<u64>>(c);
+    throughput::<sync::Mutex<u64>>(c);
+}
+
+//  every thread reads a whole table under the lock, the case elision is meant for: the
+//  transactions don't conflict, so they run in parallel where plain locking serialises them
+#[cfg(feature = "htm")]
+fn bench_elision(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lock_elision");
+    for threads in THREADS {
+        group.throughput(Throughput::Elements(threads as u64 * OPS_PER_THREAD));
+        let lock = SpinLock::new([1u64; 16]);
+        group.bench_with_input(BenchmarkId::new("lock", threads), &threads, |b, &n| {
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                thread::scope(|s| {
+                    for _ in 0..n {
+                        s.spawn(|| {
+                            for _ in 0..iters * OPS_PER_THREAD {
+                                black_box(lock.lock().unwrap().iter().sum::<u64>());
+                            }
+                        });
+                    }
+                });
+                start.elapsed()
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("elided", threads), &threads, |b, &n| {
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                thread::scope(|s| {
+                    for _ in 0..n {
+                        s.spawn(|| {
+                            for _ in 0..iters * OPS_PER_THREAD {
+                                black_box(lock.lock_elided(|table| table.iter().sum::<u64>()))
+                                    .unwrap();
+                            }
+                        });
+                    }
+                });
+                start.elapsed()
+            });
+        });
+    }
+    group.finish();
+}
+
+#[cfg(feature = "htm")]
+criterion_group!(benches, bench_locks, bench_elision);
+#[cfg(not(feature = "htm"))]
+criterion_group!(benches, bench_locks);
+criterion_main!(benches);
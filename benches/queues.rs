@@ -0,0 +1,104 @@
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use atomics::bounded_queue::{CondvarQueue, SharedQueue};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const MESSAGES: u64 = 10_000;
+const CAPACITY: usize = 64;
+
+//  the operations both queues have in common, so the pipeline is written once
+trait BenchQueue: Sync {
+    const NAME: &'static str;
+    fn new() -> Self;
+    fn put(&self, value: u64);
+    fn get(&self) -> Option<u64>;
+    fn close(&self);
+}
+
+impl BenchQueue for SharedQueue<u64, Box<[u64]>> {
+    const NAME: &'static str = "semaphore";
+    fn new() -> Self {
+        SharedQueue::with_capacity(CAPACITY)
+    }
+    fn put(&self, value: u64) {
+        SharedQueue::put(self, value).unwrap();
+    }
+    fn get(&self) -> Option<u64> {
+        SharedQueue::get(self).ok()
+    }
+    fn close(&self) {
+        SharedQueue::close(self);
+    }
+}
+
+impl BenchQueue for CondvarQueue<u64> {
+    const NAME: &'static str = "condvar";
+    fn new() -> Self {
+        CondvarQueue::with_capacity(CAPACITY)
+    }
+    fn put(&self, value: u64) {
+        CondvarQueue::put(self, value).unwrap();
+    }
+    fn get(&self) -> Option<u64> {
+        CondvarQueue::get(self).ok()
+    }
+    fn close(&self) {
+        CondvarQueue::close(self);
+    }
+}
+
+//  `MESSAGES` messages per iteration, split evenly between the producers, through a fresh queue
+fn pipeline<Q: BenchQueue>(iters: u64, producers: u64, consumers: u64) -> Duration {
+    let queue = Q::new();
+    let queue = &queue;
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..consumers {
+            s.spawn(move || {
+                while let Some(value) = queue.get() {
+                    black_box(value);
+                }
+            });
+        }
+        thread::scope(|s| {
+            for _ in 0..producers {
+                s.spawn(move || {
+                    for _ in 0..iters {
+                        for value in 0..MESSAGES / producers {
+                            queue.put(value);
+                        }
+                    }
+                });
+            }
+        });
+        queue.close();
+    });
+    start.elapsed()
+}
+
+fn ratios<Q: BenchQueue>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("queue_pipeline/{}", Q::NAME));
+    group.throughput(Throughput::Elements(MESSAGES));
+    for (producers, consumers) in [(1, 1), (4, 1), (1, 4), (4, 4)] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}p{}c", producers, consumers)),
+            &(producers, consumers),
+            |b, &(producers, consumers)| {
+                b.iter_custom(|iters| pipeline::<Q>(iters, producers, consumers))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_queues(c: &mut Criterion) {
+    ratios::<SharedQueue<u64, Box<[u64]>>>(c);
+    ratios::<CondvarQueue<u64>>(c);
+}
+
+criterion_group!(benches, bench_queues);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use atomics::mutex::SpinLock;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+//  bare lock words so the two acquisition strategies can be compared without the guard and
+//  poisoning overhead of SpinLock
+struct RawLock {
+    locked: AtomicBool,
+}
+
+impl RawLock {
+    //  the lock as it was before the test-and-test-and-set change: every waiter hammers the cache
+    //  line with compare-exchanges
+    fn lock_cas_only(&self) {
+        while self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+    }
+
+    fn lock_ttas(&self) {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+const OPS_PER_THREAD: u64 = 1_000;
+
+fn contended<F>(threads: usize, iters: u64, op: F) -> Duration
+where
+    F: Fn() + Sync,
+{
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..iters * OPS_PER_THREAD {
+                    op();
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn bench_ttas(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spinlock_contended");
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(BenchmarkId::new("cas_only", threads), &threads, |b, &n| {
+            let lock = RawLock {
+                locked: AtomicBool::new(false),
+            };
+            b.iter_custom(|iters| {
+                contended(n, iters, || {
+                    lock.lock_cas_only();
+                    black_box(&lock);
+                    lock.unlock();
+                })
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("ttas", threads), &threads, |b, &n| {
+            let lock = RawLock {
+                locked: AtomicBool::new(false),
+            };
+            b.iter_custom(|iters| {
+                contended(n, iters, || {
+                    lock.lock_ttas();
+                    black_box(&lock);
+                    lock.unlock();
+                })
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("spin_lock", threads), &threads, |b, &n| {
+            let lock = SpinLock::new(0u64);
+            b.iter_custom(|iters| {
+                contended(n, iters, || {
+                    *lock.lock().unwrap() += 1;
+                })
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_ttas);
+criterion_main!(benches);
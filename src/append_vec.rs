@@ -0,0 +1,205 @@
+#![allow(dead_code)]
+
+//  A vector that threads can push to and read from at the same time without locking. Storage is a
+//  fixed table of chunk pointers where chunk `b` holds 2^b slots, so element `i` lives in chunk
+//  log2(i + 1) and nothing is ever moved once written. A push reserves an index, allocates the
+//  chunk if it is the first one there, writes the value and then marks the slot ready.
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize},
+};
+
+const CHUNKS: usize = usize::BITS as usize;
+
+struct Slot<T> {
+    ready: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub struct AppendVec<T> {
+    chunks: [AtomicPtr<Slot<T>>; CHUNKS],
+    //  indices handed out so far, including pushes that have not finished writing
+    reserved: AtomicUsize,
+}
+
+unsafe impl<T> Send for AppendVec<T> where T: Send {}
+//  values pushed on one thread are read by reference on others
+unsafe impl<T> Sync for AppendVec<T> where T: Send + Sync {}
+
+impl<T> Default for AppendVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> AppendVec<T> {
+    pub fn new() -> Self {
+        Self {
+            chunks: std::array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+            reserved: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends the value and returns its index. Never blocks and never moves existing elements.
+    pub fn push(&self, value: T) -> usize {
+        let index = self
+            .reserved
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let (chunk, offset) = locate(index);
+        let slot = unsafe { &*self.chunk(chunk).add(offset) };
+        unsafe { (*slot.value.get()).write(value) };
+        slot.ready.store(true, std::sync::atomic::Ordering::Release);
+        index
+    }
+
+    /// `None` if `index` has not been pushed yet, or its push is still in progress.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let (chunk, offset) = locate(index);
+        let slots = self.chunks[chunk].load(std::sync::atomic::Ordering::Acquire);
+        if slots.is_null() {
+            return None;
+        }
+        let slot = unsafe { &*slots.add(offset) };
+        if !slot.ready.load(std::sync::atomic::Ordering::Acquire) {
+            return None;
+        }
+        Some(unsafe { (*slot.value.get()).assume_init_ref() })
+    }
+
+    /// Counts pushes that have started, so `get` can still return `None` below it for a moment.
+    pub fn len(&self) -> usize {
+        self.reserved.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The elements in index order, stopping at the first one whose push has not finished.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len()).map_while(move |index| self.get(index))
+    }
+
+    //  the chunk's slots, allocating them if this is the first push to reach it
+    fn chunk(&self, chunk: usize) -> *mut Slot<T> {
+        let slots = self.chunks[chunk].load(std::sync::atomic::Ordering::Acquire);
+        if !slots.is_null() {
+            return slots;
+        }
+        let new: Box<[Slot<T>]> = (0..1usize << chunk)
+            .map(|_| Slot {
+                ready: AtomicBool::new(false),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        let new = Box::into_raw(new) as *mut Slot<T>;
+        match self.chunks[chunk].compare_exchange(
+            ptr::null_mut(),
+            new,
+            std::sync::atomic::Ordering::AcqRel,
+            std::sync::atomic::Ordering::Acquire,
+        ) {
+            Ok(_) => new,
+            Err(winner) => {
+                //  another push allocated the chunk first; nothing was written to ours
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(new, 1 << chunk)) });
+                winner
+            }
+        }
+    }
+}
+
+//  element `i` is at `offset` in chunk `b`, where i + 1 = 2^b + offset
+fn locate(index: usize) -> (usize, usize) {
+    let position = index.checked_add(1).expect("index overflow");
+    let chunk = position.ilog2() as usize;
+    (chunk, position - (1 << chunk))
+}
+
+impl<T> Drop for AppendVec<T> {
+    fn drop(&mut self) {
+        for (chunk, slots) in self.chunks.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if slots.is_null() {
+                continue;
+            }
+            let mut slots =
+                unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(slots, 1 << chunk)) };
+            for slot in slots.iter_mut() {
+                if *slot.ready.get_mut() {
+                    unsafe { slot.value.get_mut().assume_init_drop() };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, sync::Arc};
+
+    use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_locate() {
+        assert_eq!(locate(0), (0, 0));
+        assert_eq!(locate(1), (1, 0));
+        assert_eq!(locate(2), (1, 1));
+        assert_eq!(locate(6), (2, 3));
+        assert_eq!(locate(7), (3, 0));
+        assert_eq!(
+            locate(usize::MAX - 1),
+            (CHUNKS - 1, (1 << (CHUNKS - 1)) - 1)
+        );
+    }
+
+    #[test]
+    fn test_push_and_get() {
+        let vec = AppendVec::new();
+        assert_eq!(vec.get(0), None);
+        for i in 0..100 {
+            assert_eq!(vec.push(i * 2), i);
+        }
+        assert_eq!(vec.len(), 100);
+        assert_eq!(vec.get(37), Some(&74));
+        assert_eq!(vec.get(100), None);
+        assert_eq!(
+            vec.iter().copied().collect::<Vec<_>>(),
+            (0..200).step_by(2).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_concurrent_push() {
+        let vec = Arc::new(AppendVec::new());
+        let pushing = Arc::clone(&vec);
+        //  every thread checks that each value it pushed reads back at the index it was given,
+        //  while the others keep growing the vector
+        stress(4, 1000).run(move |ctx| {
+            for i in 0..ctx.iterations {
+                let value = ctx.thread * ctx.iterations + i;
+                let index = pushing.push(value);
+                assert_eq!(pushing.get(index), Some(&value));
+            }
+        });
+        let mut values: Vec<_> = vec.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, (0..4000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_drops() {
+        let counted = Rc::new(());
+        let vec = AppendVec::new();
+        for _ in 0..10 {
+            vec.push(Rc::clone(&counted));
+        }
+        assert_eq!(Rc::strong_count(&counted), 11);
+        drop(vec);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+}
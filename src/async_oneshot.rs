@@ -0,0 +1,241 @@
+#![allow(dead_code)]
+
+//  The one-shot channel again, but for async code: instead of parking a thread the receiver is a
+//  future, which leaves its task's waker behind for the sender to wake.
+
+use std::{
+    cell::UnsafeCell,
+    error::Error,
+    fmt,
+    future::Future,
+    mem::MaybeUninit,
+    pin::Pin,
+    sync::{atomic::AtomicU8, Arc},
+    task::{Context, Poll, Waker},
+};
+
+use crate::mutex::SpinLock;
+
+const EMPTY: u8 = 0;
+const READY: u8 = 1;
+const TAKEN: u8 = 2;
+//  one of the halves was dropped before the message got through
+const SENDER_GONE: u8 = 3;
+const RECEIVER_GONE: u8 = 4;
+
+/// The sender was dropped without sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sender dropped without sending")
+    }
+}
+
+impl Error for Canceled {}
+
+struct Shared<T> {
+    state: AtomicU8,
+    message: UnsafeCell<MaybeUninit<T>>,
+    waker: SpinLock<Option<Waker>>,
+}
+
+unsafe impl<T> Sync for Shared<T> where T: Send {}
+
+impl<T> Shared<T> {
+    fn wake(&self) {
+        let waker = self.waker.lock().unwrap().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: AtomicU8::new(EMPTY),
+        message: UnsafeCell::new(MaybeUninit::uninit()),
+        waker: SpinLock::new(None),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+            sent: false,
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    sent: bool,
+}
+
+impl<T> Sender<T> {
+    /// Hands the message back if the receiver has already been dropped.
+    pub fn send(mut self, message: T) -> Result<(), T> {
+        //  nobody else touches the message until the state says it is ready
+        unsafe { (*self.shared.message.get()).write(message) };
+        if self
+            .shared
+            .state
+            .compare_exchange(
+                EMPTY,
+                READY,
+                std::sync::atomic::Ordering::Release,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return Err(unsafe { (*self.shared.message.get()).assume_init_read() });
+        }
+        self.sent = true;
+        self.shared.wake();
+        Ok(())
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.shared.state.load(std::sync::atomic::Ordering::Relaxed) == RECEIVER_GONE
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if !self.sent
+            && self
+                .shared
+                .state
+                .compare_exchange(
+                    EMPTY,
+                    SENDER_GONE,
+                    std::sync::atomic::Ordering::Relaxed,
+                    std::sync::atomic::Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            self.shared.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    fn try_take(&self) -> Option<Result<T, Canceled>> {
+        match self.shared.state.compare_exchange(
+            READY,
+            TAKEN,
+            std::sync::atomic::Ordering::Acquire,
+            std::sync::atomic::Ordering::Relaxed,
+        ) {
+            Ok(_) => Some(Ok(unsafe {
+                (*self.shared.message.get()).assume_init_read()
+            })),
+            Err(SENDER_GONE) => Some(Err(Canceled)),
+            Err(TAKEN) => panic!("polled after completion"),
+            Err(_) => None,
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(result) = self.try_take() {
+            return Poll::Ready(result);
+        }
+        {
+            let mut waker = self.shared.waker.lock().unwrap();
+            match &mut *waker {
+                Some(waker) => waker.clone_from(cx.waker()),
+                None => *waker = Some(cx.waker().clone()),
+            }
+        }
+        //  the sender may have finished before the waker was in place, in which case it had
+        //  nothing to wake
+        match self.try_take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let state = self
+            .shared
+            .state
+            .swap(RECEIVER_GONE, std::sync::atomic::Ordering::Acquire);
+        if state == READY {
+            unsafe { (*self.shared.message.get()).assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        task::Wake,
+        thread::{self, Thread},
+        time::Duration,
+    };
+
+    use super::*;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_send_before_poll() {
+        let (sender, receiver) = channel();
+        sender.send(42).unwrap();
+        assert_eq!(block_on(receiver), Ok(42));
+    }
+
+    #[test]
+    fn test_send_wakes_receiver() {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(vec![42]).unwrap();
+        });
+        assert_eq!(block_on(receiver), Ok(vec![42]));
+    }
+
+    #[test]
+    fn test_canceled() {
+        let (sender, receiver) = channel::<i32>();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(sender);
+        });
+        assert_eq!(block_on(receiver), Err(Canceled));
+
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert!(sender.is_canceled());
+        assert_eq!(sender.send(42), Err(42));
+    }
+}
@@ -1,14 +1,27 @@
 #![allow(dead_code)]
 
-use std::sync::{Arc, Mutex};
-use std_semaphore::Semaphore;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    marker::PhantomData,
+    sync::{atomic::AtomicUsize, Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+#[cfg(feature = "stats")]
+use std::time::Instant;
+
+use crate::semaphore::Semaphore;
 
 const CAPACITY: usize = 5;
 
-struct BoundedQueue<T> {
-    buffer: [T; CAPACITY],
+//  the buffer is either a fixed array or a boxed slice sized at runtime
+struct BoundedQueue<T, B = [T; CAPACITY]> {
+    buffer: B,
     producer: usize,
     consumer: usize,
+    //  producer == consumer both when empty and when full, so the length is kept separately
+    len: usize,
+    _marker: PhantomData<T>,
 }
 
 impl<T> BoundedQueue<T>
@@ -16,87 +29,899 @@ where
     T: Copy + Default,
 {
     pub fn new() -> Self {
+        Self::from_buffer([T::default(); CAPACITY])
+    }
+}
+
+impl<T> BoundedQueue<T, Box<[T]>>
+where
+    T: Copy + Default,
+{
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        Self::from_buffer(vec![T::default(); capacity].into_boxed_slice())
+    }
+}
+
+impl<T, B> BoundedQueue<T, B>
+where
+    T: Copy,
+    B: AsRef<[T]> + AsMut<[T]>,
+{
+    fn from_buffer(buffer: B) -> Self {
         Self {
-            buffer: [T::default(); CAPACITY],
+            buffer,
             producer: 0,
             consumer: 0,
+            len: 0,
+            _marker: PhantomData,
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.buffer.as_ref().len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
     pub fn put(&mut self, value: T) {
-        self.buffer[self.producer] = value;
-        self.producer = (self.producer + 1) % CAPACITY;
+        debug_assert!(!self.is_full(), "put on a full queue");
+        self.buffer.as_mut()[self.producer] = value;
+        self.producer = (self.producer + 1) % self.capacity();
+        self.len += 1;
     }
 
     pub fn get(&mut self) -> T {
-        let value = self.buffer[self.consumer];
-        self.consumer = (self.consumer + 1) % CAPACITY;
+        debug_assert!(!self.is_empty(), "get on an empty queue");
+        let value = self.buffer.as_ref()[self.consumer];
+        self.consumer = (self.consumer + 1) % self.capacity();
+        self.len -= 1;
         value
     }
+
+    /// Removes everything currently buffered, oldest first. The items are gone even if the
+    /// iterator is dropped before reaching the end.
+    pub fn drain(&mut self) -> Drain<'_, T, B> {
+        Drain { queue: self }
+    }
+}
+
+struct Drain<'a, T, B>
+where
+    T: Copy,
+    B: AsRef<[T]> + AsMut<[T]>,
+{
+    queue: &'a mut BoundedQueue<T, B>,
+}
+
+impl<T, B> Iterator for Drain<'_, T, B>
+where
+    T: Copy,
+    B: AsRef<[T]> + AsMut<[T]>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        Some(self.queue.get())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.queue.len(), Some(self.queue.len()))
+    }
+}
+
+impl<T, B> Drop for Drain<'_, T, B>
+where
+    T: Copy,
+    B: AsRef<[T]> + AsMut<[T]>,
+{
+    fn drop(&mut self) {
+        self.queue.consumer = self.queue.producer;
+        self.queue.len = 0;
+    }
 }
 
-struct SharedQueue<T> {
-    queue: Mutex<BoundedQueue<T>>,
+/// A bounded queue for many producers and consumers: a ring under a mutex, with a semaphore
+/// counting the free slots and another counting the filled ones.
+pub struct SharedQueue<T, B = [T; CAPACITY]> {
+    queue: Mutex<BoundedQueue<T, B>>,
     producer: Semaphore,
     consumer: Semaphore,
+    #[cfg(feature = "stats")]
+    stats: stats::Counters,
+}
+
+impl<T> Default for SharedQueue<T>
+where
+    T: Copy + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> SharedQueue<T>
 where
     T: Copy + Default,
 {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        Self::from_queue(BoundedQueue::new())
+    }
+}
+
+impl<T> SharedQueue<T, Box<[T]>>
+where
+    T: Copy + Default,
+{
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::from_queue(BoundedQueue::with_capacity(capacity))
+    }
+}
+
+impl<T, B> SharedQueue<T, B>
+where
+    T: Copy,
+    B: AsRef<[T]> + AsMut<[T]>,
+{
+    fn from_queue(queue: BoundedQueue<T, B>) -> Self {
         Self {
-            queue: Mutex::new(BoundedQueue::new()),
-            producer: Semaphore::new(CAPACITY as isize),
+            producer: Semaphore::new(queue.capacity()),
             consumer: Semaphore::new(0),
+            queue: Mutex::new(queue),
+            #[cfg(feature = "stats")]
+            stats: stats::Counters::new(),
+        }
+    }
+
+    /// Hands the value back if the queue is closed, including while waiting for a free slot.
+    pub fn put(&self, value: T) -> Result<(), Closed<T>> {
+        if !self.acquire_slot(|| self.wait_for_room(|| self.producer.acquire())) {
+            return Err(Closed(value));
+        }
+        self.enqueue(value);
+        Ok(())
+    }
+
+    /// Once the queue is closed, keeps returning what is left and then `Err` instead of blocking.
+    pub fn get(&self) -> Result<T, Closed> {
+        if !self.consumer.acquire() {
+            return Err(Closed(()));
+        }
+        let value = self.dequeue();
+        Ok(value)
+    }
+
+    /// Hands the value back instead of waiting if the queue is full, or closed.
+    pub fn try_put(&self, value: T) -> Result<(), TryPutError<T>> {
+        if !self.acquire_slot(|| self.producer.try_acquire()) {
+            //  a closed queue stays closed, so checking after the fact can't misreport it
+            return Err(if self.is_closed() {
+                TryPutError::Closed(value)
+            } else {
+                TryPutError::Full(value)
+            });
+        }
+        self.enqueue(value);
+        Ok(())
+    }
+
+    pub fn try_get(&self) -> Option<T> {
+        if !self.consumer.try_acquire() {
+            return None;
+        }
+        let value = self.dequeue();
+        Some(value)
+    }
+
+    /// Like `put`, but hands the value back if no slot frees up within `timeout`.
+    pub fn put_timeout(&self, value: T, timeout: Duration) -> Result<(), PutTimeoutError<T>> {
+        if !self.acquire_slot(|| self.wait_for_room(|| self.producer.acquire_timeout(timeout))) {
+            return Err(if self.is_closed() {
+                PutTimeoutError::Closed(value)
+            } else {
+                PutTimeoutError::Timeout(value)
+            });
+        }
+        self.enqueue(value);
+        Ok(())
+    }
+
+    /// Like `get`, but returns `None` if nothing arrives within `timeout`.
+    pub fn get_timeout(&self, timeout: Duration) -> Option<T> {
+        if !self.consumer.acquire_timeout(timeout) {
+            return None;
+        }
+        let value = self.dequeue();
+        Some(value)
+    }
+
+    /// Puts every value, taking the lock and the free slots once per run of values that fit
+    /// instead of once per value. If the queue is closed part way, the values that did not go in
+    /// are handed back.
+    pub fn put_all(&self, values: impl IntoIterator<Item = T>) -> Result<(), Closed<Vec<T>>> {
+        let mut values = values.into_iter().peekable();
+        while values.peek().is_some() {
+            let slots = if self.is_closed() {
+                0
+            } else {
+                self.wait_for_room(|| self.producer.acquire_up_to(usize::MAX))
+            };
+            if slots == 0 || self.is_closed() {
+                self.producer.release_n(slots);
+                return Err(Closed(values.collect()));
+            }
+            let mut put = 0;
+            {
+                let mut queue = self.queue.lock().unwrap();
+                for value in values.by_ref().take(slots) {
+                    queue.put(value);
+                    put += 1;
+                }
+                #[cfg(feature = "stats")]
+                self.stats.produced(put, queue.len());
+            }
+            self.producer.release_n(slots - put);
+            self.consumer.release_n(put);
+        }
+        Ok(())
+    }
+
+    /// Blocks until there is something to take, then takes up to `max` items at once. An empty
+    /// batch means the queue is closed and drained.
+    pub fn get_batch(&self, max: usize) -> Vec<T> {
+        if max == 0 {
+            return vec![];
         }
+        let items = self.consumer.acquire_up_to(max);
+        let batch = {
+            let mut queue = self.queue.lock().unwrap();
+            (0..items).map(|_| queue.get()).collect()
+        };
+        #[cfg(feature = "stats")]
+        self.stats.consumed(items);
+        self.producer.release_n(items);
+        batch
+    }
+
+    /// Stops new items from going in and wakes everyone blocked on the queue. Consumers can still
+    /// drain what was put before.
+    pub fn close(&self) {
+        self.producer.close();
+        self.consumer.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.producer.is_closed()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> QueueStats {
+        let depth = self.queue.lock().unwrap().len();
+        self.stats.snapshot(depth)
+    }
+
+    fn enqueue(&self, value: T) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.put(value);
+        #[cfg(feature = "stats")]
+        self.stats.produced(1, queue.len());
+        drop(queue);
+        self.consumer.release();
     }
+
+    fn dequeue(&self) -> T {
+        let value = self.queue.lock().unwrap().get();
+        #[cfg(feature = "stats")]
+        self.stats.consumed(1);
+        self.producer.release();
+        value
+    }
+
+    fn wait_for_room<R>(&self, acquire: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        let acquired = acquire();
+        #[cfg(feature = "stats")]
+        self.stats.producer_blocked(start.elapsed());
+        acquired
+    }
+
+    fn acquire_slot(&self, acquire: impl FnOnce() -> bool) -> bool {
+        if self.is_closed() || !acquire() {
+            return false;
+        }
+        //  a producer that was already waiting can still be handed the slot a consumer frees up
+        //  after the close
+        if self.is_closed() {
+            self.producer.release();
+            return false;
+        }
+        true
+    }
+}
+
+/// A snapshot of a queue's counters.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub produced: u64,
+    pub consumed: u64,
+    /// Items waiting in the queue.
+    pub depth: usize,
+    /// The most items the queue has held at once.
+    pub high_water_mark: usize,
+    /// Total time producers spent waiting for room.
+    pub producer_block_time: Duration,
 }
 
-fn producer(shared_queue: Arc<SharedQueue<i32>>, loops: usize) {
+#[cfg(feature = "stats")]
+mod stats {
+    use std::{
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::QueueStats;
+
+    pub(super) struct Counters {
+        produced: AtomicU64,
+        consumed: AtomicU64,
+        high_water_mark: AtomicUsize,
+        producer_block_nanos: AtomicU64,
+    }
+
+    impl Counters {
+        pub(super) const fn new() -> Self {
+            Self {
+                produced: AtomicU64::new(0),
+                consumed: AtomicU64::new(0),
+                high_water_mark: AtomicUsize::new(0),
+                producer_block_nanos: AtomicU64::new(0),
+            }
+        }
+
+        //  called with the queue locked, so `depth` is exact
+        pub(super) fn produced(&self, items: usize, depth: usize) {
+            self.produced.fetch_add(items as u64, Ordering::Relaxed);
+            self.high_water_mark.fetch_max(depth, Ordering::Relaxed);
+        }
+
+        pub(super) fn consumed(&self, items: usize) {
+            self.consumed.fetch_add(items as u64, Ordering::Relaxed);
+        }
+
+        pub(super) fn producer_blocked(&self, blocked: Duration) {
+            let nanos = u64::try_from(blocked.as_nanos()).unwrap_or(u64::MAX);
+            self.producer_block_nanos
+                .fetch_add(nanos, Ordering::Relaxed);
+        }
+
+        pub(super) fn snapshot(&self, depth: usize) -> QueueStats {
+            QueueStats {
+                produced: self.produced.load(Ordering::Relaxed),
+                consumed: self.consumed.load(Ordering::Relaxed),
+                depth,
+                high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+                producer_block_time: Duration::from_nanos(
+                    self.producer_block_nanos.load(Ordering::Relaxed),
+                ),
+            }
+        }
+    }
+}
+
+/// Returned by `put` with the value that was refused, and by `get` once a closed queue is empty.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Closed<T = ()>(pub T);
+
+/// Returned by `try_put` with the value that was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryPutError<T> {
+    /// Every slot was taken.
+    Full(T),
+    /// The queue was closed.
+    Closed(T),
+}
+
+/// Returned by `put_timeout` with the value that was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PutTimeoutError<T> {
+    /// No slot freed up in time.
+    Timeout(T),
+    /// The queue was closed, either before the call or while it was waiting.
+    Closed(T),
+}
+
+/// The same ring under a single mutex, but producers and consumers wait on a condition variable
+/// for their side instead of on a semaphore. A put or get takes the lock once rather than once for
+/// the ring and once for each semaphore.
+pub struct CondvarQueue<T> {
+    state: Mutex<CondvarState<T>>,
+    not_full: Condvar,
+    not_empty: Condvar,
+}
+
+struct CondvarState<T> {
+    queue: BoundedQueue<T, Box<[T]>>,
+    closed: bool,
+}
+
+impl<T> CondvarQueue<T>
+where
+    T: Copy + Default,
+{
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(CondvarState {
+                queue: BoundedQueue::with_capacity(capacity),
+                closed: false,
+            }),
+            not_full: Condvar::new(),
+            not_empty: Condvar::new(),
+        }
+    }
+
+    /// Hands the value back if the queue is closed, including while waiting for a free slot.
+    pub fn put(&self, value: T) -> Result<(), Closed<T>> {
+        let mut state = self
+            .not_full
+            .wait_while(self.state.lock().unwrap(), |state| {
+                state.queue.is_full() && !state.closed
+            })
+            .unwrap();
+        if state.closed {
+            return Err(Closed(value));
+        }
+        state.queue.put(value);
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Once the queue is closed, keeps returning what is left and then `Err` instead of blocking.
+    pub fn get(&self) -> Result<T, Closed> {
+        let mut state = self
+            .not_empty
+            .wait_while(self.state.lock().unwrap(), |state| {
+                state.queue.is_empty() && !state.closed
+            })
+            .unwrap();
+        if state.queue.is_empty() {
+            return Err(Closed(()));
+        }
+        let value = state.queue.get();
+        drop(state);
+        self.not_full.notify_one();
+        Ok(value)
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_full.notify_all();
+        self.not_empty.notify_all();
+    }
+}
+
+/// Several `SharedQueue`s behind one front, so producers mostly contend on different locks.
+/// Producers are spread over the shards round-robin, or by key to keep related items in order.
+/// Consumers start at a different shard each time and take from the first one that has anything.
+pub struct ShardedQueue<T> {
+    shards: Box<[SharedQueue<T, Box<[T]>>]>,
+    //  counts the items across all shards, so a consumer can block once instead of on one shard
+    items: Semaphore,
+    next_put: AtomicUsize,
+    next_get: AtomicUsize,
+}
+
+impl<T> ShardedQueue<T>
+where
+    T: Copy + Default,
+{
+    pub fn new(shards: usize, capacity_per_shard: usize) -> Self {
+        assert!(shards > 0, "need at least one shard");
+        Self {
+            shards: (0..shards)
+                .map(|_| SharedQueue::with_capacity(capacity_per_shard))
+                .collect(),
+            items: Semaphore::new(0),
+            next_put: AtomicUsize::new(0),
+            next_get: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks while the chosen shard is full, even if another one has room.
+    pub fn put(&self, value: T) -> Result<(), Closed<T>> {
+        let shard = self
+            .next_put
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.put_into(shard, value)
+    }
+
+    /// Items with the same key go to the same shard, so they come out in the order they went in
+    /// as long as there is one consumer.
+    pub fn put_keyed(&self, key: impl Hash, value: T) -> Result<(), Closed<T>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.put_into(hasher.finish() as usize, value)
+    }
+
+    fn put_into(&self, shard: usize, value: T) -> Result<(), Closed<T>> {
+        self.shards[shard % self.shards.len()].put(value)?;
+        self.items.release();
+        Ok(())
+    }
+
+    /// Once the queue is closed, keeps returning what is left in any shard and then `Err`.
+    pub fn get(&self) -> Result<T, Closed> {
+        if !self.items.acquire() {
+            return Err(Closed(()));
+        }
+        //  the permit guarantees an item somewhere, but another consumer may take the one this
+        //  scan was about to reach, so keep going round until one turns up
+        let start = self
+            .next_get
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let shards = self.shards.len();
+        loop {
+            for i in 0..shards {
+                if let Some(value) = self.shards[(start + i) % shards].try_get() {
+                    return Ok(value);
+                }
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    pub fn close(&self) {
+        for shard in self.shards.iter() {
+            shard.close();
+        }
+        self.items.close();
+    }
+}
+
+fn producer<B>(shared_queue: Arc<SharedQueue<i32, B>>, loops: usize)
+where
+    B: AsRef<[i32]> + AsMut<[i32]>,
+{
     for i in 0..loops {
-        shared_queue.producer.acquire();
-        shared_queue.queue.lock().unwrap().put(i as i32);
-        shared_queue.consumer.release();
+        shared_queue.put(i as i32).unwrap();
         println!("Produced: {}", i);
     }
 }
 
-fn consumer(shared_queue: Arc<SharedQueue<i32>>, loops: usize) {
+fn consumer<B>(shared_queue: Arc<SharedQueue<i32, B>>, loops: usize)
+where
+    B: AsRef<[i32]> + AsMut<[i32]>,
+{
     for _ in 0..loops {
-        shared_queue.consumer.acquire();
-        let value = shared_queue.queue.lock().unwrap().get();
-        shared_queue.producer.release();
+        let value = shared_queue.get().unwrap();
         println!("Consumed: {}", value);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+
     use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_len_and_drain() {
+        let mut queue = BoundedQueue::<i32>::new();
+        assert!(queue.is_empty());
+        for i in 0..CAPACITY as i32 {
+            queue.put(i);
+        }
+        assert!(queue.is_full());
+        assert_eq!(queue.get(), 0);
+        queue.put(5);
+        assert_eq!(queue.len(), CAPACITY);
+        assert_eq!(queue.drain().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert!(queue.is_empty());
+
+        queue.put(6);
+        queue.put(7);
+        assert_eq!(queue.drain().next(), Some(6));
+        assert_eq!(queue.len(), 0);
+        queue.put(8);
+        assert_eq!(queue.get(), 8);
+    }
 
     #[test]
     fn test_bounded_queue() {
         let shared_queue = Arc::new(SharedQueue::<i32>::new());
-        let producer_queue_1 = shared_queue.clone();
-        let producer_queue_2 = shared_queue.clone();
-        let consumer_queue = shared_queue.clone();
 
-        let loops = 100;
-
-        let producer_handle_1 = std::thread::spawn(move || {
-            producer(producer_queue_1, loops);
+        //  thread 0 consumes everything the other two threads produce
+        stress(3, 100).run(move |ctx| {
+            let queue = Arc::clone(&shared_queue);
+            if ctx.thread == 0 {
+                consumer(queue, ctx.iterations * (ctx.threads - 1));
+            } else {
+                producer(queue, ctx.iterations);
+            }
         });
-        let producer_handle_2 = std::thread::spawn(move || {
-            producer(producer_queue_2, loops);
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        for capacity in [1, 3, 64] {
+            let shared_queue = Arc::new(SharedQueue::<i32, _>::with_capacity(capacity));
+            assert_eq!(shared_queue.queue.lock().unwrap().capacity(), capacity);
+
+            stress(3, 100).run(move |ctx| {
+                let queue = Arc::clone(&shared_queue);
+                if ctx.thread == 0 {
+                    consumer(queue, ctx.iterations * (ctx.threads - 1));
+                } else {
+                    producer(queue, ctx.iterations);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_try_put_try_get() {
+        let shared_queue = SharedQueue::<i32, _>::with_capacity(2);
+        assert_eq!(shared_queue.try_get(), None);
+        assert_eq!(shared_queue.try_put(1), Ok(()));
+        assert_eq!(shared_queue.try_put(2), Ok(()));
+        assert_eq!(shared_queue.try_put(3), Err(TryPutError::Full(3)));
+        assert_eq!(shared_queue.try_get(), Some(1));
+        assert_eq!(shared_queue.try_put(3), Ok(()));
+        assert_eq!(shared_queue.get(), Ok(2));
+        assert_eq!(shared_queue.try_get(), Some(3));
+        assert_eq!(shared_queue.try_get(), None);
+    }
+
+    #[test]
+    fn test_put_get_timeout() {
+        let shared_queue = Arc::new(SharedQueue::<i32, _>::with_capacity(1));
+        let timeout = Duration::from_millis(10);
+        assert_eq!(shared_queue.get_timeout(timeout), None);
+        assert_eq!(shared_queue.put_timeout(1, timeout), Ok(()));
+        assert_eq!(
+            shared_queue.put_timeout(2, timeout),
+            Err(PutTimeoutError::Timeout(2))
+        );
+
+        let consuming = {
+            let shared_queue = Arc::clone(&shared_queue);
+            thread::spawn(move || {
+                thread::sleep(timeout);
+                shared_queue.get()
+            })
+        };
+        assert_eq!(shared_queue.put_timeout(2, Duration::from_secs(10)), Ok(()));
+        assert_eq!(consuming.join().unwrap(), Ok(1));
+        assert_eq!(shared_queue.get_timeout(timeout), Some(2));
+    }
+
+    #[test]
+    fn test_close() {
+        let shared_queue = Arc::new(SharedQueue::<i32, _>::with_capacity(2));
+        //  the consumers only know when to stop because the queue gets closed
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let shared_queue = Arc::clone(&shared_queue);
+                thread::spawn(move || {
+                    let mut consumed = vec![];
+                    while let Ok(value) = shared_queue.get() {
+                        consumed.push(value);
+                    }
+                    consumed
+                })
+            })
+            .collect();
+        let producers: Vec<_> = (0..2)
+            .map(|_| {
+                let shared_queue = Arc::clone(&shared_queue);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        shared_queue.put(i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        shared_queue.close();
+        let mut consumed: Vec<_> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        consumed.sort();
+        let mut expected: Vec<_> = (0..100).chain(0..100).collect();
+        expected.sort();
+        assert_eq!(consumed, expected);
+
+        assert_eq!(shared_queue.put(1), Err(Closed(1)));
+        assert_eq!(shared_queue.try_put(1), Err(TryPutError::Closed(1)));
+        assert_eq!(
+            shared_queue.put_timeout(1, Duration::from_millis(10)),
+            Err(PutTimeoutError::Closed(1))
+        );
+        assert_eq!(shared_queue.get(), Err(Closed(())));
+    }
+
+    #[test]
+    fn test_batches() {
+        let shared_queue = Arc::new(SharedQueue::<i32, _>::with_capacity(4));
+        let producing = {
+            let shared_queue = Arc::clone(&shared_queue);
+            thread::spawn(move || {
+                shared_queue.put_all(0..100).unwrap();
+                shared_queue.close();
+            })
+        };
+        let mut consumed = vec![];
+        loop {
+            let batch = shared_queue.get_batch(8);
+            if batch.is_empty() {
+                break;
+            }
+            assert!(batch.len() <= 4);
+            consumed.extend(batch);
+        }
+        producing.join().unwrap();
+        assert_eq!(consumed, (0..100).collect::<Vec<_>>());
+        assert_eq!(shared_queue.put_all([1, 2]), Err(Closed(vec![1, 2])));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_stats() {
+        let shared_queue = Arc::new(SharedQueue::<i32, _>::with_capacity(2));
+        shared_queue.put_all([1, 2]).unwrap();
+        let producing = {
+            let shared_queue = Arc::clone(&shared_queue);
+            thread::spawn(move || shared_queue.put(3).unwrap())
+        };
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(shared_queue.get(), Ok(1));
+        producing.join().unwrap();
+        assert_eq!(shared_queue.get_batch(2), vec![2, 3]);
+        shared_queue.put(4).unwrap();
+
+        let stats = shared_queue.stats();
+        assert!(stats.producer_block_time >= Duration::from_millis(5));
+        assert_eq!(
+            stats,
+            QueueStats {
+                produced: 4,
+                consumed: 3,
+                depth: 1,
+                high_water_mark: 2,
+                producer_block_time: stats.producer_block_time,
+            }
+        );
+    }
+
+    #[test]
+    fn test_condvar_queue() {
+        let queue = Arc::new(CondvarQueue::with_capacity(2));
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let mut consumed = vec![];
+                    while let Ok(value) = queue.get() {
+                        consumed.push(value);
+                    }
+                    consumed
+                })
+            })
+            .collect();
+        stress(2, 100).run({
+            let queue = Arc::clone(&queue);
+            move |ctx| {
+                for i in 0..ctx.iterations {
+                    queue.put(i).unwrap();
+                }
+            }
         });
-        let consumer_handle = std::thread::spawn(move || {
-            consumer(consumer_queue, loops * 2);
+        queue.close();
+        let mut consumed: Vec<_> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        consumed.sort();
+        let mut expected: Vec<_> = (0..100).chain(0..100).collect();
+        expected.sort();
+        assert_eq!(consumed, expected);
+        assert_eq!(queue.put(1), Err(Closed(1)));
+    }
+
+    #[test]
+    fn test_sharded_queue() {
+        let queue = Arc::new(ShardedQueue::new(4, 2));
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let mut consumed = vec![];
+                    while let Ok(value) = queue.get() {
+                        consumed.push(value);
+                    }
+                    consumed
+                })
+            })
+            .collect();
+        stress(4, 100).run({
+            let queue = Arc::clone(&queue);
+            move |ctx| {
+                for i in 0..ctx.iterations {
+                    queue.put(ctx.thread * ctx.iterations + i).unwrap();
+                }
+            }
         });
+        queue.close();
+        let mut consumed: Vec<_> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        consumed.sort();
+        assert_eq!(consumed, (0..400).collect::<Vec<_>>());
+        assert_eq!(queue.put(1), Err(Closed(1)));
+    }
 
-        producer_handle_1.join().unwrap();
-        producer_handle_2.join().unwrap();
-        consumer_handle.join().unwrap();
+    #[test]
+    fn test_sharded_queue_keyed() {
+        let queue = Arc::new(ShardedQueue::new(4, 2));
+        let producing = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                for i in 0..100 {
+                    queue.put_keyed(i % 3, i).unwrap();
+                }
+                queue.close();
+            })
+        };
+        let mut per_key = vec![vec![]; 3];
+        while let Ok(value) = queue.get() {
+            per_key[value % 3].push(value);
+        }
+        producing.join().unwrap();
+        for (key, values) in per_key.into_iter().enumerate() {
+            assert_eq!(values, (key..100).step_by(3).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_close_wakes_producer() {
+        let shared_queue = Arc::new(SharedQueue::<i32, _>::with_capacity(1));
+        shared_queue.put(1).unwrap();
+        let producing = {
+            let shared_queue = Arc::clone(&shared_queue);
+            thread::spawn(move || shared_queue.put(2))
+        };
+        let producing_with_timeout = {
+            let shared_queue = Arc::clone(&shared_queue);
+            thread::spawn(move || shared_queue.put_timeout(3, Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(10));
+        shared_queue.close();
+        assert_eq!(producing.join().unwrap(), Err(Closed(2)));
+        assert_eq!(
+            producing_with_timeout.join().unwrap(),
+            Err(PutTimeoutError::Closed(3))
+        );
+        assert_eq!(shared_queue.get(), Ok(1));
+        assert_eq!(shared_queue.get(), Err(Closed(())));
     }
 }
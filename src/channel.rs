@@ -1,45 +1,149 @@
 #![allow(dead_code)]
 
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicBool};
+use std::{
+    mem::MaybeUninit,
+    sync::mpsc::{SendError, TryRecvError},
+};
+#[cfg(not(loom))]
+use std::{
+    sync::mpsc::{RecvError, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+#[cfg(not(loom))]
+use crate::parking;
+use crate::sync::{atomic::AtomicU8, const_fn, UnsafeCell};
+
+//  a message moves through the channel as EMPTY -> WRITING -> READY -> TAKEN
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+const TAKEN: u8 = 3;
 
 pub struct Channel<T> {
-    ready: AtomicBool,
+    state: AtomicU8,
     message: UnsafeCell<MaybeUninit<T>>,
 }
 
-unsafe impl<T> Sync for Channel<T> {}
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl<T> Channel<T> {
-    fn new() -> Self {
-        Self {
-            ready: AtomicBool::new(false),
-            message: UnsafeCell::new(MaybeUninit::uninit()),
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                state: AtomicU8::new(EMPTY),
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+            }
         }
     }
 
-    fn send(&self, message: T) {
-        //  if there is already a message in the channel, panic
-        if self.ready.swap(true, std::sync::atomic::Ordering::Acquire) {
-            panic!("cannot send more than one message in a channel");
+    /// Hands the message back if one has already been sent.
+    pub fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        //  claim the channel before writing, so a second sender is turned away
+        if self
+            .state
+            .compare_exchange(
+                EMPTY,
+                WRITING,
+                std::sync::atomic::Ordering::Relaxed,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return Err(SendError(message));
         }
-        //  store the message in the channel and set the flag
-        unsafe { (*self.message.get()).write(message) };
+        //  store the message in the channel and then publish it
+        self.message
+            .with_mut(|slot| unsafe { (*slot).write(message) });
+        self.state
+            .store(READY, std::sync::atomic::Ordering::Release);
+        #[cfg(not(loom))]
+        parking::unpark_all(self.addr());
+        Ok(())
     }
 
-    fn receive(&self) -> T {
-        //  if the ready flag is not set, panic
-        if !self.ready.swap(false, std::sync::atomic::Ordering::Acquire) {
-            panic!("there is either no message stored in the channel or the message has already been read");
+    /// `Empty` until the message has been sent, `Disconnected` once it has been received since a
+    /// one-shot channel will never carry another.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.state.compare_exchange(
+            READY,
+            TAKEN,
+            std::sync::atomic::Ordering::Acquire,
+            std::sync::atomic::Ordering::Relaxed,
+        ) {
+            Ok(_) => Ok(self
+                .message
+                .with(|slot| unsafe { (*slot).assume_init_read() })),
+            Err(TAKEN) => Err(TryRecvError::Disconnected),
+            Err(_) => Err(TryRecvError::Empty),
         }
-        unsafe { (*self.message.get()).assume_init_read() }
     }
 
-    fn is_ready(&self) -> bool {
-        self.ready.load(std::sync::atomic::Ordering::Acquire)
+    pub fn send(&self, message: T) {
+        if self.try_send(message).is_err() {
+            panic!("cannot send more than one message in a channel");
+        }
+    }
+
+    pub fn receive(&self) -> T {
+        self.try_recv().unwrap_or_else(|_| {
+            panic!("there is either no message stored in the channel or the message has already been read")
+        })
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.load(std::sync::atomic::Ordering::Acquire) == READY
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+//  the blocking receives sleep in the parking table, which is not available under loom
+#[cfg(not(loom))]
+impl<T> Channel<T> {
+    /// Blocks until the message arrives. Returns `Err` if it has already been received.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            //  checked under the parking bucket's lock, so the sender can't publish and wake
+            //  everyone between the check and this thread going to sleep
+            let validate = || self.state.load(std::sync::atomic::Ordering::Relaxed) < READY;
+            match deadline {
+                Some(deadline) if Instant::now() >= deadline => {
+                    return Err(RecvTimeoutError::Timeout)
+                }
+                Some(deadline) => parking::park_until(self.addr(), validate, deadline),
+                None => parking::park(self.addr(), validate),
+            };
+        }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod test {
     use std::thread;
 
@@ -52,6 +156,60 @@ mod test {
         assert_eq!(channel.receive(), 42);
     }
 
+    #[test]
+    fn test_try_send_try_recv() {
+        let channel = Channel::new();
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+        assert!(channel.try_send(1).is_ok());
+        assert_eq!(channel.try_send(2), Err(SendError(2)));
+        assert_eq!(channel.try_recv(), Ok(1));
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_recv_blocks_until_sent() {
+        let channel = Channel::new();
+        thread::scope(|s| {
+            let receivers: Vec<_> = (0..2).map(|_| s.spawn(|| channel.recv())).collect();
+            thread::sleep(Duration::from_millis(10));
+            channel.send(42);
+            let results: Vec<_> = receivers
+                .into_iter()
+                .map(|receiver| receiver.join().unwrap())
+                .collect();
+            assert!(results.contains(&Ok(42)));
+            assert!(results.contains(&Err(RecvError)));
+        });
+        assert_eq!(channel.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let channel = Channel::new();
+        assert_eq!(
+            channel.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                channel.send(42);
+            });
+            assert_eq!(channel.recv_timeout(Duration::from_secs(10)), Ok(42));
+        });
+        assert_eq!(
+            channel.recv_deadline(Instant::now()),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_channel_static() {
+        static CHANNEL: Channel<i32> = Channel::new();
+        thread::spawn(|| CHANNEL.send(42)).join().unwrap();
+        assert_eq!(CHANNEL.receive(), 42);
+    }
+
     #[test]
     fn test_channel_threads() {
         let channel = Channel::new();
@@ -71,3 +229,24 @@ mod test {
         });
     }
 }
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn loom_channel_message_visible_once_ready() {
+        loom::model(|| {
+            let channel = Arc::new(Channel::new());
+            let sender = Arc::clone(&channel);
+            let handle = thread::spawn(move || sender.send(vec![42]));
+            while !channel.is_ready() {
+                thread::yield_now();
+            }
+            assert_eq!(channel.receive(), vec![42]);
+            handle.join().unwrap();
+        });
+    }
+}
@@ -1,30 +1,71 @@
 #![allow(dead_code)]
 
 use std::{
-    cell::UnsafeCell,
+    cell::{Cell, UnsafeCell},
     marker::PhantomData,
     mem::MaybeUninit,
     rc::Rc,
-    sync::atomic::AtomicBool,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, AtomicUsize},
+        mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError},
+        Arc,
+    },
     thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
+use crate::parking;
+
 pub struct Channel<T> {
     ready: AtomicBool,
+    //  set when either half goes away without having done its part, so the other one doesn't wait
+    //  forever or send into the void
+    disconnected: AtomicBool,
+    //  clones of the sender race for the one message; whichever claims it first writes it
+    claimed: AtomicBool,
+    senders: AtomicUsize,
     message: UnsafeCell<MaybeUninit<T>>,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
 
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returned by `Sender::send` with the message that was not delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOnceError<T> {
+    /// Another clone of the sender got its message in first.
+    AlreadyCompleted(T),
+    /// The receiver was dropped.
+    Disconnected(T),
+}
+
+impl<T> SendOnceError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendOnceError::AlreadyCompleted(message) | SendOnceError::Disconnected(message) => {
+                message
+            }
+        }
+    }
+}
+
 impl<T> Channel<T> {
-    fn new() -> Self {
+    pub const fn new() -> Self {
         Channel {
             ready: AtomicBool::new(false),
+            disconnected: AtomicBool::new(false),
+            claimed: AtomicBool::new(false),
+            senders: AtomicUsize::new(1),
             message: UnsafeCell::new(MaybeUninit::uninit()),
         }
     }
 
-    fn split<'a>(&'a mut self) -> (Sender<'a, T>, Receiver<'a, T>) {
+    pub fn split<'a>(&'a mut self) -> (Sender<'a, T>, Receiver<'a, T>) {
         *self = Self::new();
         let sender = Sender {
             channel: self,
@@ -36,6 +77,54 @@ impl<T> Channel<T> {
         };
         (sender, receiver)
     }
+
+    /// Like `split`, but the halves share a heap-allocated channel instead of borrowing one, so
+    /// they can be stored or moved to any thread. The channel is freed once both are dropped.
+    pub fn new_pair() -> (OwnedSender<T>, OwnedReceiver<T>) {
+        let channel = Arc::new(Self::new());
+        let sender = OwnedSender {
+            channel: Arc::clone(&channel),
+            sent: false,
+        };
+        (sender, OwnedReceiver { channel })
+    }
+
+    //  the receiving thread isn't known up front, so waiting happens in the parking table keyed
+    //  by the channel's address
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn put(&self, message: T) -> Result<(), SendOnceError<T>> {
+        if self.disconnected.load(std::sync::atomic::Ordering::Acquire) {
+            return Err(SendOnceError::Disconnected(message));
+        }
+        if self
+            .claimed
+            .swap(true, std::sync::atomic::Ordering::Relaxed)
+        {
+            return Err(SendOnceError::AlreadyCompleted(message));
+        }
+        unsafe { (*self.message.get()).write(message) };
+        self.ready.store(true, std::sync::atomic::Ordering::Release);
+        Ok(())
+    }
+
+    fn take(&self) -> Result<T, TryRecvError> {
+        //  a sent message is still delivered if the sender has gone since
+        if self.ready.swap(false, std::sync::atomic::Ordering::Acquire) {
+            Ok(unsafe { (*self.message.get()).assume_init_read() })
+        } else if self.disconnected.load(std::sync::atomic::Ordering::Acquire) {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    fn disconnect(&self) {
+        self.disconnected
+            .store(true, std::sync::atomic::Ordering::Release);
+    }
 }
 
 impl<T> Drop for Channel<T> {
@@ -46,17 +135,47 @@ impl<T> Drop for Channel<T> {
     }
 }
 
+/// Can be cloned to let several threads race to complete the channel: the first `send` wins.
 pub struct Sender<'a, T> {
     channel: &'a Channel<T>,
     recv_thread: Thread,
 }
 
 impl<T> Sender<'_, T> {
-    fn send(self, message: T) {
-        unsafe { (*self.channel.message.get()).write(message) };
+    /// Hands the message back if another clone has already sent, or the receiver has been
+    /// dropped.
+    pub fn send(self, message: T) -> Result<(), SendOnceError<T>> {
+        self.channel.put(message)
+    }
+}
+
+impl<T> Clone for Sender<'_, T> {
+    fn clone(&self) -> Self {
         self.channel
-            .ready
-            .store(true, std::sync::atomic::Ordering::Release);
+            .senders
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self {
+            channel: self.channel,
+            recv_thread: self.recv_thread.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        //  the receiver only gives up once the last clone is gone without anything sent
+        if self
+            .channel
+            .senders
+            .fetch_sub(1, std::sync::atomic::Ordering::AcqRel)
+            == 1
+            && !self
+                .channel
+                .claimed
+                .load(std::sync::atomic::Ordering::Relaxed)
+        {
+            self.channel.disconnect();
+        }
         self.recv_thread.unpark();
     }
 }
@@ -67,27 +186,291 @@ pub struct Receiver<'a, T> {
 }
 
 impl<T> Receiver<'_, T> {
-    fn is_ready(&self) -> bool {
+    pub fn is_ready(&self) -> bool {
         self.channel
             .ready
             .load(std::sync::atomic::Ordering::Acquire)
     }
 
-    fn receive(self) -> T {
-        while !self
-            .channel
+    /// Returns `Err` if the sender was dropped without sending.
+    pub fn receive(self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like `receive`, but gives up after `timeout`. The receiver is only borrowed so it can be
+    /// tried again.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(Instant::now() + timeout)
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.channel.take() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+                None => thread::park(),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<'_, T> {
+    fn drop(&mut self) {
+        self.channel.disconnect();
+    }
+}
+
+pub struct OwnedSender<T> {
+    channel: Arc<Channel<T>>,
+    sent: bool,
+}
+
+impl<T> OwnedSender<T> {
+    /// Hands the message back if the receiver has already been dropped.
+    pub fn send(mut self, message: T) -> Result<(), SendError<T>> {
+        self.channel
+            .put(message)
+            .map_err(|err| SendError(err.into_inner()))?;
+        self.sent = true;
+        Ok(())
+    }
+}
+
+impl<T> Drop for OwnedSender<T> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.channel.disconnect();
+        }
+        parking::unpark_all(self.channel.addr());
+    }
+}
+
+pub struct OwnedReceiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> OwnedReceiver<T> {
+    pub fn is_ready(&self) -> bool {
+        self.channel
             .ready
-            .swap(false, std::sync::atomic::Ordering::Acquire)
+            .load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Returns `Err` if the sender was dropped without sending.
+    pub fn receive(self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like `receive`, but gives up after `timeout`. The receiver is only borrowed so it can be
+    /// tried again.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(Instant::now() + timeout)
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.channel.take() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let validate = || {
+                !self.is_ready()
+                    && !self
+                        .channel
+                        .disconnected
+                        .load(std::sync::atomic::Ordering::Relaxed)
+            };
+            match deadline {
+                Some(deadline) => {
+                    if Instant::now() >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    parking::park_until(self.channel.addr(), validate, deadline);
+                }
+                None => {
+                    parking::park(self.channel.addr(), validate);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for OwnedReceiver<T> {
+    fn drop(&mut self) {
+        self.channel.disconnect();
+    }
+}
+
+const EMPTY: u8 = 0;
+const FULL: u8 = 1;
+
+/// Carries one message at a time, but any number in total: once the receiver has taken a message
+/// the slot is free for the sender's next one, so the halves don't need to be split again.
+pub struct ReusableChannel<T> {
+    state: AtomicU8,
+    disconnected: AtomicBool,
+    message: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T> Sync for ReusableChannel<T> where T: Send {}
+
+impl<T> Default for ReusableChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ReusableChannel<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(EMPTY),
+            disconnected: AtomicBool::new(false),
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Any message still waiting from a previous split is dropped.
+    pub fn split<'a>(&'a mut self) -> (ReusableSender<'a, T>, ReusableReceiver<'a, T>) {
+        *self = Self::new();
+        let sender = ReusableSender {
+            channel: self,
+            recv_thread: thread::current(),
+            _not_sync: PhantomData,
+        };
+        let receiver = ReusableReceiver {
+            channel: self,
+            _no_send: PhantomData,
+        };
+        (sender, receiver)
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(std::sync::atomic::Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for ReusableChannel<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == FULL {
+            unsafe { self.message.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+pub struct ReusableSender<'a, T> {
+    channel: &'a ReusableChannel<T>,
+    recv_thread: Thread,
+    //  `send` assumes it is the only one writing the slot, so the sender can't be shared
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> ReusableSender<'_, T> {
+    /// Hands the message back if the previous one has not been received yet or the receiver has
+    /// been dropped.
+    pub fn send(&self, message: T) -> Result<(), TrySendError<T>> {
+        if self.channel.is_disconnected() {
+            return Err(TrySendError::Disconnected(message));
+        }
+        //  each half only ever moves the state away from its own value, so plain loads and stores
+        //  are enough
+        if self
+            .channel
+            .state
+            .load(std::sync::atomic::Ordering::Acquire)
+            != EMPTY
         {
+            return Err(TrySendError::Full(message));
+        }
+        unsafe { (*self.channel.message.get()).write(message) };
+        self.channel
+            .state
+            .store(FULL, std::sync::atomic::Ordering::Release);
+        self.recv_thread.unpark();
+        Ok(())
+    }
+}
+
+impl<T> Drop for ReusableSender<'_, T> {
+    fn drop(&mut self) {
+        self.channel
+            .disconnected
+            .store(true, std::sync::atomic::Ordering::Release);
+        self.recv_thread.unpark();
+    }
+}
+
+pub struct ReusableReceiver<'a, T> {
+    channel: &'a ReusableChannel<T>,
+    _no_send: PhantomData<Rc<()>>,
+}
+
+impl<T> ReusableReceiver<'_, T> {
+    /// Returns `Err` once the sender has been dropped and its last message received.
+    pub fn receive(&self) -> Result<T, RecvError> {
+        while self
+            .channel
+            .state
+            .load(std::sync::atomic::Ordering::Acquire)
+            != FULL
+        {
+            if self.channel.is_disconnected() {
+                //  the sender may have sent one last message just before going away
+                if self
+                    .channel
+                    .state
+                    .load(std::sync::atomic::Ordering::Acquire)
+                    != FULL
+                {
+                    return Err(RecvError);
+                }
+                break;
+            }
             thread::park();
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        let message = unsafe { (*self.channel.message.get()).assume_init_read() };
+        self.channel
+            .state
+            .store(EMPTY, std::sync::atomic::Ordering::Release);
+        Ok(message)
+    }
+}
+
+impl<T> Drop for ReusableReceiver<'_, T> {
+    fn drop(&mut self) {
+        self.channel
+            .disconnected
+            .store(true, std::sync::atomic::Ordering::Release);
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::thread;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
 
     use super::*;
 
@@ -95,8 +478,8 @@ mod test {
     fn test_channel() {
         let mut channel = Channel::new();
         let (sender, receiver) = channel.split();
-        sender.send(42);
-        assert_eq!(receiver.receive(), 42);
+        sender.send(42).unwrap();
+        assert_eq!(receiver.receive(), Ok(42));
     }
 
     #[test]
@@ -105,9 +488,184 @@ mod test {
         let (sender, receiver) = channel.split();
         thread::scope(|s| {
             s.spawn(|| {
-                sender.send(42);
+                sender.send(42).unwrap();
+            });
+            assert_eq!(receiver.receive(), Ok(42));
+        });
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                sender.send(42).unwrap();
+            });
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(42));
+        });
+    }
+
+    #[test]
+    fn test_reusable_channel_rounds() {
+        let mut channel = ReusableChannel::new();
+        let (sender, receiver) = channel.split();
+        //  the sender isn't Sync, so it moves to the sending thread and comes back at the end
+        let sender = thread::scope(|s| {
+            let sending = s.spawn(move || {
+                for i in 0..100 {
+                    let mut message = i;
+                    while let Err(TrySendError::Full(rejected)) = sender.send(message) {
+                        message = rejected;
+                        thread::yield_now();
+                    }
+                }
+                sender
+            });
+            for i in 0..100 {
+                assert_eq!(receiver.receive(), Ok(i));
+            }
+            sending.join().unwrap()
+        });
+        sender.send(100).unwrap();
+        assert_eq!(sender.send(101), Err(TrySendError::Full(101)));
+        assert_eq!(receiver.receive(), Ok(100));
+    }
+
+    #[test]
+    fn test_owned_pair() {
+        let (sender, receiver) = Channel::new_pair();
+        let receiving = thread::spawn(move || receiver.receive());
+        thread::spawn(move || sender.send(vec![42]).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(receiving.join().unwrap(), Ok(vec![42]));
+
+        //  the unreceived message goes away with the last half
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = Channel::new_pair();
+        assert!(sender.send(CountDrops(Arc::clone(&drops))).is_ok());
+        assert!(receiver.is_ready());
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(receiver);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_owned_recv_timeout() {
+        let (sender, receiver) = Channel::new_pair();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        let sending = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(42).unwrap();
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(42));
+        sending.join().unwrap();
+
+        let (sender, receiver) = Channel::<i32>::new_pair();
+        drop(sender);
+        assert_eq!(
+            receiver.recv_deadline(Instant::now() + Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    struct CountDrops(Arc<AtomicUsize>);
+
+    impl Drop for CountDrops {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_reusable_channel_drops() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut channel = ReusableChannel::new();
+        {
+            let (sender, receiver) = channel.split();
+            sender.send(CountDrops(Arc::clone(&drops))).unwrap();
+            drop(receiver.receive().unwrap());
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+            //  left in the channel when the halves go away
+            sender.send(CountDrops(Arc::clone(&drops))).unwrap();
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        let (sender, receiver) = channel.split();
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        sender.send(CountDrops(Arc::clone(&drops))).unwrap();
+        drop((sender, receiver));
+        drop(channel);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_first_send_wins() {
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        let results: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let sender = sender.clone();
+                    s.spawn(move || sender.send(i))
+                })
+                .collect();
+            drop(sender);
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let winner = receiver.receive().unwrap();
+        for (i, result) in results.into_iter().enumerate() {
+            if i == winner {
+                assert_eq!(result, Ok(()));
+            } else {
+                assert_eq!(result, Err(SendOnceError::AlreadyCompleted(i)));
+            }
+        }
+
+        //  the receiver keeps waiting while any clone is left
+        let (sender, receiver) = channel.split();
+        drop(sender.clone());
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        drop(sender);
+        assert_eq!(receiver.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let mut channel = Channel::<i32>::new();
+        let (sender, receiver) = channel.split();
+        drop(sender);
+        assert_eq!(receiver.receive(), Err(RecvError));
+
+        let (sender, receiver) = channel.split();
+        drop(receiver);
+        assert_eq!(sender.send(1), Err(SendOnceError::Disconnected(1)));
+
+        let (sender, receiver) = Channel::<i32>::new_pair();
+        let receiving = thread::spawn(move || receiver.receive());
+        thread::sleep(Duration::from_millis(10));
+        drop(sender);
+        assert_eq!(receiving.join().unwrap(), Err(RecvError));
+
+        let mut channel = ReusableChannel::new();
+        let (sender, receiver) = channel.split();
+        thread::scope(|s| {
+            s.spawn(move || {
+                sender.send(1).unwrap();
             });
-            assert_eq!(receiver.receive(), 42);
         });
+        assert_eq!(receiver.receive(), Ok(1));
+        assert_eq!(receiver.receive(), Err(RecvError));
     }
 }
@@ -0,0 +1,184 @@
+#![allow(dead_code)]
+
+use std::{
+    fs, io,
+    thread::{self, JoinHandle},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// Where to place a producer/consumer pair of threads relative to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    SameCore,
+    DifferentCores,
+    DifferentNodes,
+}
+
+/// The cores the current process is allowed to run on.
+pub fn available_cores() -> Vec<usize> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) == 0 {
+            return (0..libc::CPU_SETSIZE as usize)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect();
+        }
+    }
+    let count = thread::available_parallelism().map_or(1, |n| n.get());
+    (0..count).collect()
+}
+
+/// The NUMA nodes of the machine, restricted to the cores in `available_cores`. Machines (or
+/// platforms) without NUMA information are reported as a single node.
+pub fn numa_nodes() -> Vec<NumaNode> {
+    let available = available_cores();
+    let mut nodes = vec![];
+    if let Ok(entries) = fs::read_dir("/sys/devices/system/node") {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse().ok())
+            else {
+                continue;
+            };
+            let Ok(list) = fs::read_to_string(entry.path().join("cpulist")) else {
+                continue;
+            };
+            let cpus: Vec<usize> = parse_cpu_list(&list)
+                .into_iter()
+                .filter(|cpu| available.contains(cpu))
+                .collect();
+            if !cpus.is_empty() {
+                nodes.push(NumaNode { id, cpus });
+            }
+        }
+    }
+    if nodes.is_empty() {
+        nodes.push(NumaNode {
+            id: 0,
+            cpus: available,
+        });
+    }
+    nodes.sort_by_key(|node| node.id);
+    nodes
+}
+
+/// Parses the kernel's cpu list format, e.g. `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = vec![];
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) {
+                    cpus.extend(start..=end);
+                }
+            }
+            None => cpus.extend(part.parse::<usize>().ok()),
+        }
+    }
+    cpus
+}
+
+/// Pins the calling thread to a single core.
+pub fn pin_current_thread(core: usize) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = core;
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// The core the calling thread is currently running on, if the platform can tell.
+pub fn current_core() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let cpu = unsafe { libc::sched_getcpu() };
+        usize::try_from(cpu).ok()
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Picks a pair of cores for a producer/consumer benchmark, or `None` if the machine cannot
+/// satisfy the placement (e.g. `DifferentNodes` on a single-node machine).
+pub fn pair_cores(placement: Placement) -> Option<(usize, usize)> {
+    match placement {
+        Placement::SameCore => available_cores().first().map(|&core| (core, core)),
+        Placement::DifferentCores => match available_cores()[..] {
+            [first, second, ..] => Some((first, second)),
+            _ => None,
+        },
+        Placement::DifferentNodes => match &numa_nodes()[..] {
+            [first, second, ..] => Some((first.cpus[0], second.cpus[0])),
+            _ => None,
+        },
+    }
+}
+
+/// Spawns a thread that pins itself to `core` before running `f`.
+pub fn spawn_pinned<F, T>(core: usize, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::spawn(move || {
+        pin_current_thread(core).expect("failed to pin thread");
+        f()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5"), vec![5]);
+        assert!(parse_cpu_list("").is_empty());
+    }
+
+    #[test]
+    fn test_topology_is_consistent() {
+        let cores = available_cores();
+        assert!(!cores.is_empty());
+        let nodes = numa_nodes();
+        assert!(nodes
+            .iter()
+            .flat_map(|node| &node.cpus)
+            .all(|cpu| cores.contains(cpu)));
+        let (first, second) = pair_cores(Placement::SameCore).unwrap();
+        assert_eq!(first, second);
+        if let Some((first, second)) = pair_cores(Placement::DifferentCores) {
+            assert_ne!(first, second);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_spawn_pinned_runs_on_core() {
+        let core = *available_cores().last().unwrap();
+        let ran_on = spawn_pinned(core, current_core).join().unwrap();
+        assert_eq!(ran_on, Some(core));
+    }
+}
@@ -0,0 +1,75 @@
+//  Intel's Restricted Transactional Memory through inline assembly, since the intrinsics are not
+//  stable yet. When a transaction aborts the CPU rolls back its memory writes and registers and
+//  resumes right after `xbegin` with the abort status in eax, so to the caller `begin` simply
+//  returns a second time with a different value.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::asm;
+
+/// What `begin` returns inside a transaction.
+pub(crate) const STARTED: u32 = u32::MAX;
+/// Set in the abort status if the transaction ended through `abort`.
+pub(crate) const ABORT_EXPLICIT: u32 = 1 << 0;
+/// Set in the abort status if the transaction may succeed when retried.
+pub(crate) const ABORT_RETRY: u32 = 1 << 1;
+
+/// The code passed to `xabort` by `abort`, found in the top byte of the abort status.
+pub(crate) const ABORT_CODE: u8 = 0xff;
+
+/// Whether the CPU supports RTM. Many CPUs that have it ship with it disabled by microcode.
+pub(crate) fn available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("rtm")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+pub(crate) fn abort_code(status: u32) -> u8 {
+    (status >> 24) as u8
+}
+
+/// # Safety
+///
+/// `available` must have returned true, and every `STARTED` must be followed by `end` or `abort`.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) unsafe fn begin() -> u32 {
+    let status: u32;
+    asm!("mov eax, -1", "xbegin 2f", "2:", out("eax") status, options(nostack));
+    status
+}
+
+/// # Safety
+///
+/// Must be inside a transaction.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) unsafe fn end() {
+    asm!("xend", options(nostack));
+}
+
+/// Aborts the current transaction with `ABORT_CODE`, which makes `begin` return again.
+///
+/// # Safety
+///
+/// Must be inside a transaction.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) unsafe fn abort() {
+    asm!("xabort 0xff", options(nostack));
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) unsafe fn begin() -> u32 {
+    unreachable!("RTM is only available on x86_64")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) unsafe fn end() {}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) unsafe fn abort() {}
@@ -0,0 +1,30 @@
+pub mod append_vec;
+pub mod async_oneshot;
+pub mod bounded_queue;
+pub mod channel;
+#[cfg(not(loom))]
+pub mod channel_split;
+pub mod cpu;
+#[cfg(feature = "htm")]
+mod htm;
+#[cfg(not(loom))]
+pub mod mpmc;
+pub mod mutex;
+#[cfg(not(loom))]
+pub mod parking;
+#[cfg(not(loom))]
+pub mod pipeline;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(not(loom))]
+pub mod rpc;
+pub mod rwlock;
+pub mod semaphore;
+pub mod seqlock;
+pub mod shutdown;
+#[cfg(test)]
+mod stress;
+mod sync;
+pub mod vec_deque;
+#[cfg(not(loom))]
+pub mod watch;
@@ -1,22 +1,16 @@
-mod bounded_queue;
-mod channel;
-mod channel_split;
-mod mutex;
-mod semaphore;
-
-use mutex::SpinLock;
+use atomics::{mutex::SpinLock, shutdown::Shutdown};
 
 fn run_mutex_example() {
     let spin_lock = SpinLock::new(0);
     std::thread::scope(|s| {
         s.spawn(|| {
-            let mut guard = spin_lock.lock();
+            let mut guard = spin_lock.lock().unwrap();
             *guard = 2;
             println!("thread 1 acquired the spin lock");
             println!("the value is {}", *guard);
         });
         s.spawn(|| {
-            let guard = spin_lock.lock();
+            let guard = spin_lock.lock().unwrap();
             println!("thread 2 acquired the spin lock");
             println!("the value is {}", *guard);
         });
@@ -24,5 +18,15 @@ fn run_mutex_example() {
 }
 
 fn main() {
-    run_mutex_example();
+    let shutdown = Shutdown::install().expect("failed to install signal handlers");
+    let examples: [(&str, fn()); 1] = [("mutex", run_mutex_example)];
+    for (name, example) in examples {
+        if shutdown.is_triggered() {
+            println!("shutdown requested, skipping the {} example", name);
+            continue;
+        }
+        let _work = shutdown.track();
+        example();
+    }
+    shutdown.drain();
 }
@@ -0,0 +1,1150 @@
+#![allow(dead_code)]
+
+//  A bounded multi-producer multi-consumer channel over a ring of slots, after Dmitry Vyukov's
+//  bounded queue. Every slot carries a stamp saying which position it is ready for: twice the
+//  position when a sender may write it, one more than that once the message is in and a receiver
+//  may read it. (Doubling keeps the two apart even when there is a single slot.) Claiming a
+//  position is a single CAS on head or tail, so senders and receivers only contend among
+//  themselves, and the stamp handoff publishes the value. Blocked threads wait in the parking
+//  table, keyed by the address of the index they are waiting on.
+
+use std::{
+    cell::UnsafeCell,
+    error::Error,
+    fmt,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicUsize},
+        Arc,
+    },
+    time::{Duration, Instant},
+    vec,
+};
+
+use crate::parking;
+
+#[repr(align(128))]
+struct CachePadded<T>(T);
+
+struct Slot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Shared<T> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    slots: Box<[Slot<T>]>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    closed: AtomicBool,
+    policy: WakeupPolicy,
+    //  in fair mode receivers take a ticket and only the one being served may take a message
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    //  sends turned away because the channel was full, for spotting slow consumers
+    rejected: AtomicUsize,
+    #[cfg(feature = "stats")]
+    stats: stats::Counters,
+}
+
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+//  positions only ever grow, and at one send per nanosecond a 64-bit counter lasts for centuries,
+//  so wrapping around is not handled
+
+impl<T> Shared<T> {
+    fn slot(&self, pos: usize) -> &Slot<T> {
+        &self.slots[pos % self.slots.len()]
+    }
+
+    fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.0.load(std::sync::atomic::Ordering::Relaxed);
+        loop {
+            let slot = self.slot(tail);
+            let stamp = slot.stamp.load(std::sync::atomic::Ordering::Acquire);
+            if stamp == 2 * tail {
+                match self.tail.0.compare_exchange_weak(
+                    tail,
+                    tail + 1,
+                    std::sync::atomic::Ordering::Relaxed,
+                    std::sync::atomic::Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.stamp
+                            .store(2 * tail + 1, std::sync::atomic::Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                }
+            } else if stamp < 2 * tail {
+                //  the slot still holds the message from the previous lap
+                return Err(value);
+            } else {
+                tail = self.tail.0.load(std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut head = self.head.0.load(std::sync::atomic::Ordering::Relaxed);
+        loop {
+            let slot = self.slot(head);
+            let stamp = slot.stamp.load(std::sync::atomic::Ordering::Acquire);
+            if stamp == 2 * head + 1 {
+                match self.head.0.compare_exchange_weak(
+                    head,
+                    head + 1,
+                    std::sync::atomic::Ordering::Relaxed,
+                    std::sync::atomic::Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        //  free the slot for the sender one lap ahead
+                        slot.stamp.store(
+                            2 * (head + self.slots.len()),
+                            std::sync::atomic::Ordering::Release,
+                        );
+                        return Some(value);
+                    }
+                    Err(current) => head = current,
+                }
+            } else if stamp <= 2 * head {
+                return None;
+            } else {
+                head = self.head.0.load(std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    //  claims as many consecutive slots as there are values and free slots with a single CAS;
+    //  none of them can be taken from under us, since claiming a later position means moving the
+    //  tail past ours first
+    fn push_batch(&self, values: &mut vec::IntoIter<T>) -> usize {
+        let mut tail = self.tail.0.load(std::sync::atomic::Ordering::Relaxed);
+        loop {
+            let wanted = values.len().min(self.slots.len());
+            let free = (0..wanted)
+                .take_while(|&i| {
+                    self.slot(tail + i)
+                        .stamp
+                        .load(std::sync::atomic::Ordering::Acquire)
+                        == 2 * (tail + i)
+                })
+                .count();
+            if free == 0 {
+                if wanted == 0
+                    || self
+                        .slot(tail)
+                        .stamp
+                        .load(std::sync::atomic::Ordering::Acquire)
+                        < 2 * tail
+                {
+                    return 0;
+                }
+                tail = self.tail.0.load(std::sync::atomic::Ordering::Relaxed);
+                continue;
+            }
+            match self.tail.0.compare_exchange_weak(
+                tail,
+                tail + free,
+                std::sync::atomic::Ordering::Relaxed,
+                std::sync::atomic::Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    for pos in tail..tail + free {
+                        let slot = self.slot(pos);
+                        unsafe { (*slot.value.get()).write(values.next().unwrap()) };
+                        slot.stamp
+                            .store(2 * pos + 1, std::sync::atomic::Ordering::Release);
+                    }
+                    return free;
+                }
+                Err(current) => tail = current,
+            }
+        }
+    }
+
+    fn pop_batch(&self, buf: &mut Vec<T>, max: usize) -> usize {
+        let mut head = self.head.0.load(std::sync::atomic::Ordering::Relaxed);
+        loop {
+            let ready = (0..max.min(self.slots.len()))
+                .take_while(|&i| {
+                    self.slot(head + i)
+                        .stamp
+                        .load(std::sync::atomic::Ordering::Acquire)
+                        == 2 * (head + i) + 1
+                })
+                .count();
+            if ready == 0 {
+                if max == 0
+                    || self
+                        .slot(head)
+                        .stamp
+                        .load(std::sync::atomic::Ordering::Acquire)
+                        <= 2 * head
+                {
+                    return 0;
+                }
+                head = self.head.0.load(std::sync::atomic::Ordering::Relaxed);
+                continue;
+            }
+            match self.head.0.compare_exchange_weak(
+                head,
+                head + ready,
+                std::sync::atomic::Ordering::Relaxed,
+                std::sync::atomic::Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    buf.reserve(ready);
+                    for pos in head..head + ready {
+                        let slot = self.slot(pos);
+                        buf.push(unsafe { (*slot.value.get()).assume_init_read() });
+                        slot.stamp.store(
+                            2 * (pos + self.slots.len()),
+                            std::sync::atomic::Ordering::Release,
+                        );
+                    }
+                    return ready;
+                }
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        let tail = self.tail.0.load(std::sync::atomic::Ordering::Relaxed);
+        self.slot(tail)
+            .stamp
+            .load(std::sync::atomic::Ordering::Acquire)
+            < 2 * tail
+    }
+
+    fn is_empty(&self) -> bool {
+        let head = self.head.0.load(std::sync::atomic::Ordering::Relaxed);
+        self.slot(head)
+            .stamp
+            .load(std::sync::atomic::Ordering::Acquire)
+            <= 2 * head
+    }
+
+    //  senders wait for the head to move, receivers for the tail
+    fn send_addr(&self) -> usize {
+        &self.head as *const _ as usize
+    }
+
+    fn recv_addr(&self) -> usize {
+        &self.tail as *const _ as usize
+    }
+
+    //  for waiting, a closed channel looks the same as one whose other side has gone away. The
+    //  errors check `is_closed` to tell the two apart, and report a close over a disconnect
+    fn is_disconnected(&self, side: &AtomicUsize) -> bool {
+        side.load(std::sync::atomic::Ordering::Acquire) == 0 || self.is_closed()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    fn recv_error(&self) -> RecvError {
+        if self.is_closed() {
+            RecvError::Closed
+        } else {
+            RecvError::Disconnected
+        }
+    }
+
+    //  nothing reads the buffer once the last receiver is gone, so whatever is still in it is
+    //  dropped then rather than when the last sender goes away
+    fn drop_unreceived(&self) {
+        while self.pop().is_some() {}
+    }
+
+    //  a send racing with the last receiver's drop may land after that drop drained the buffer.
+    //  With a fence on both sides, either the receiver sees the message or the sender sees that
+    //  the receivers are gone and drops it itself.
+    fn drop_if_unreceivable(&self) {
+        fence(std::sync::atomic::Ordering::SeqCst);
+        if self.receivers.load(std::sync::atomic::Ordering::Relaxed) == 0 {
+            self.drop_unreceived();
+        }
+    }
+
+    fn close(&self) {
+        self.closed
+            .store(true, std::sync::atomic::Ordering::Release);
+        parking::unpark_all(self.send_addr());
+        parking::unpark_all(self.recv_addr());
+    }
+
+    fn wait_until_not_full(&self, deadline: Option<Instant>) {
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        let full = || self.is_full() && !self.is_disconnected(&self.receivers);
+        match deadline {
+            Some(deadline) => {
+                parking::park_until(self.send_addr(), full, deadline);
+            }
+            None => {
+                parking::park(self.send_addr(), full);
+            }
+        }
+        #[cfg(feature = "stats")]
+        self.stats.send_blocked(start.elapsed());
+    }
+
+    fn wait_until_not_empty(&self) {
+        #[cfg(feature = "stats")]
+        self.stats.receiver_waiting();
+        parking::park(self.recv_addr(), || {
+            self.is_empty() && !self.is_disconnected(&self.senders)
+        });
+        #[cfg(feature = "stats")]
+        self.stats.receiver_woken();
+    }
+
+    fn turn_addr(&self) -> usize {
+        &self.now_serving as *const _ as usize
+    }
+
+    //  waits behind the receivers that came earlier; the turn passes on when the guard drops
+    fn wait_for_turn(&self) -> Option<Turn<'_, T>> {
+        if self.policy == WakeupPolicy::Throughput {
+            return None;
+        }
+        let ticket = self
+            .next_ticket
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        while self.now_serving.load(std::sync::atomic::Ordering::Acquire) != ticket {
+            parking::park(self.turn_addr(), || {
+                self.now_serving.load(std::sync::atomic::Ordering::Acquire) != ticket
+            });
+        }
+        Some(Turn { shared: self })
+    }
+
+    #[cfg(feature = "stats")]
+    fn metrics(&self) -> ChannelMetrics {
+        //  head is read first so the difference can only come out too large, never negative
+        let head = self.head.0.load(std::sync::atomic::Ordering::Relaxed);
+        let tail = self.tail.0.load(std::sync::atomic::Ordering::Relaxed);
+        let depth = tail.saturating_sub(head).min(self.slots.len());
+        self.stats.snapshot(depth)
+    }
+}
+
+struct Turn<'a, T> {
+    shared: &'a Shared<T>,
+}
+
+impl<T> Drop for Turn<'_, T> {
+    fn drop(&mut self) {
+        self.shared
+            .now_serving
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        parking::unpark_all(self.shared.turn_addr());
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        self.drop_unreceived();
+    }
+}
+
+/// Returned by `send` and `send_all` with whatever could not be sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendError<T> {
+    /// Every receiver is gone.
+    Disconnected(T),
+    /// The channel was closed, either before the call or while it was waiting.
+    Closed(T),
+}
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Disconnected(value) | SendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Disconnected(_) => write!(f, "Disconnected(..)"),
+            SendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Disconnected(_) => write!(f, "sending on a channel with no receivers"),
+            SendError::Closed(_) => write!(f, "sending on a closed channel"),
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// Returned by `try_send` with the message that could not be sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Disconnected(_) => write!(f, "Disconnected(..)"),
+            TrySendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "sending on a full channel"),
+            TrySendError::Disconnected(_) => write!(f, "sending on a channel with no receivers"),
+            TrySendError::Closed(_) => write!(f, "sending on a closed channel"),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {}
+
+/// Returned by `send_timeout` with the message that could not be sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+    Closed(T),
+}
+
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => write!(f, "Timeout(..)"),
+            SendTimeoutError::Disconnected(_) => write!(f, "Disconnected(..)"),
+            SendTimeoutError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => write!(f, "timed out waiting on a full channel"),
+            SendTimeoutError::Disconnected(_) => {
+                write!(f, "sending on a channel with no receivers")
+            }
+            SendTimeoutError::Closed(_) => write!(f, "sending on a closed channel"),
+        }
+    }
+}
+
+impl<T> Error for SendTimeoutError<T> {}
+
+/// Returned by `recv` and `recv_batch` once the channel is empty and no more messages can come.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecvError {
+    /// Every sender is gone.
+    Disconnected,
+    /// The channel was closed.
+    Closed,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => write!(f, "receiving on an empty channel with no senders"),
+            RecvError::Closed => write!(f, "receiving on an empty, closed channel"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+/// A snapshot of a channel's counters, shared by all of its senders and receivers.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelMetrics {
+    /// Messages waiting in the channel.
+    pub depth: usize,
+    pub sent: u64,
+    pub received: u64,
+    /// Total time senders spent waiting for room.
+    pub blocked_send_time: Duration,
+    pub waiting_receivers: usize,
+}
+
+#[cfg(feature = "stats")]
+mod stats {
+    use std::{
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::ChannelMetrics;
+
+    pub(super) struct Counters {
+        sent: AtomicU64,
+        received: AtomicU64,
+        blocked_send_nanos: AtomicU64,
+        waiting_receivers: AtomicUsize,
+    }
+
+    impl Counters {
+        pub(super) const fn new() -> Self {
+            Self {
+                sent: AtomicU64::new(0),
+                received: AtomicU64::new(0),
+                blocked_send_nanos: AtomicU64::new(0),
+                waiting_receivers: AtomicUsize::new(0),
+            }
+        }
+
+        pub(super) fn sent(&self, messages: usize) {
+            self.sent.fetch_add(messages as u64, Ordering::Relaxed);
+        }
+
+        pub(super) fn received(&self, messages: usize) {
+            self.received.fetch_add(messages as u64, Ordering::Relaxed);
+        }
+
+        pub(super) fn send_blocked(&self, blocked: Duration) {
+            let nanos = u64::try_from(blocked.as_nanos()).unwrap_or(u64::MAX);
+            self.blocked_send_nanos.fetch_add(nanos, Ordering::Relaxed);
+        }
+
+        pub(super) fn receiver_waiting(&self) {
+            self.waiting_receivers.fetch_add(1, Ordering::Relaxed);
+        }
+
+        pub(super) fn receiver_woken(&self) {
+            self.waiting_receivers.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        pub(super) fn snapshot(&self, depth: usize) -> ChannelMetrics {
+            ChannelMetrics {
+                depth,
+                sent: self.sent.load(Ordering::Relaxed),
+                received: self.received.load(Ordering::Relaxed),
+                blocked_send_time: Duration::from_nanos(
+                    self.blocked_send_nanos.load(Ordering::Relaxed),
+                ),
+                waiting_receivers: self.waiting_receivers.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+/// How blocked receivers compete for messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeupPolicy {
+    /// Whichever receiver gets to a message first takes it, including ones that just arrived
+    /// while others were waiting. Fastest, but a receiver can lose every race for a while.
+    Throughput,
+    /// Receivers take messages in the order they started waiting, at the cost of waking every
+    /// waiting receiver whenever the turn passes on.
+    Fair,
+}
+
+/// Creates a channel that holds up to `capacity` messages.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    bounded_with_policy(capacity, WakeupPolicy::Throughput)
+}
+
+pub fn bounded_with_policy<T>(capacity: usize, policy: WakeupPolicy) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be non-zero");
+    let slots = (0..capacity)
+        .map(|i| Slot {
+            stamp: AtomicUsize::new(2 * i),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        })
+        .collect();
+    let shared = Arc::new(Shared {
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+        slots,
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        policy,
+        next_ticket: AtomicUsize::new(0),
+        now_serving: AtomicUsize::new(0),
+        rejected: AtomicUsize::new(0),
+        #[cfg(feature = "stats")]
+        stats: stats::Counters::new(),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Blocks while the channel is full. Hands the message back once every receiver is gone or
+    /// the channel is closed.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send_until(value, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(value) | SendTimeoutError::Timeout(value) => {
+                SendError::Disconnected(value)
+            }
+            SendTimeoutError::Closed(value) => SendError::Closed(value),
+        })
+    }
+
+    /// Hands the message back right away if the channel is full.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let shared = &*self.shared;
+        if shared.is_closed() {
+            return Err(TrySendError::Closed(value));
+        }
+        if shared.is_disconnected(&shared.receivers) {
+            return Err(TrySendError::Disconnected(value));
+        }
+        match shared.push(value) {
+            Ok(()) => {
+                #[cfg(feature = "stats")]
+                shared.stats.sent(1);
+                shared.drop_if_unreceivable();
+                parking::unpark_one(shared.recv_addr(), |_| {});
+                Ok(())
+            }
+            Err(value) => {
+                shared
+                    .rejected
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Err(TrySendError::Full(value))
+            }
+        }
+    }
+
+    /// Like `send`, but hands the message back if the channel is still full after `timeout`.
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(value, Some(Instant::now() + timeout))
+    }
+
+    fn send_until(&self, value: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let shared = &*self.shared;
+        let mut value = value;
+        loop {
+            match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(value)) => {
+                    return Err(SendTimeoutError::Disconnected(value))
+                }
+                Err(TrySendError::Closed(value)) => return Err(SendTimeoutError::Closed(value)),
+                Err(TrySendError::Full(rejected)) => value = rejected,
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(SendTimeoutError::Timeout(value));
+            }
+            shared.wait_until_not_full(deadline);
+        }
+    }
+
+    /// Sends the values in order, blocking whenever the channel is full. Runs of values go in
+    /// with a single claim on the channel and a single wakeup, as far as there is room for them.
+    /// The iterator is read at most a channel's worth of values ahead of what has been sent.
+    /// Hands back whatever was not sent, including the rest of the iterator, if every receiver
+    /// is gone or the channel is closed.
+    pub fn send_all(&self, values: impl IntoIterator<Item = T>) -> Result<(), SendError<Vec<T>>> {
+        let shared = &*self.shared;
+        let mut values = values.into_iter();
+        loop {
+            let mut chunk = values
+                .by_ref()
+                .take(shared.slots.len())
+                .collect::<Vec<_>>()
+                .into_iter();
+            if chunk.as_slice().is_empty() {
+                return Ok(());
+            }
+            while !chunk.as_slice().is_empty() {
+                if shared.is_disconnected(&shared.receivers) {
+                    let unsent = chunk.chain(values).collect();
+                    return Err(if shared.is_closed() {
+                        SendError::Closed(unsent)
+                    } else {
+                        SendError::Disconnected(unsent)
+                    });
+                }
+                match shared.push_batch(&mut chunk) {
+                    0 => {
+                        shared
+                            .rejected
+                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        shared.wait_until_not_full(None);
+                    }
+                    sent => {
+                        #[cfg(feature = "stats")]
+                        shared.stats.sent(sent);
+                        shared.drop_if_unreceivable();
+                        if sent == 1 {
+                            parking::unpark_one(shared.recv_addr(), |_| {});
+                        } else {
+                            parking::unpark_all(shared.recv_addr());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        self.shared.metrics()
+    }
+
+    /// Ends the stream for every handle, without waiting for them to be dropped: later sends
+    /// hand their message back, and receivers get what was already sent before `Err`. A send
+    /// racing with `close` may still get its message in.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// How many sends, from any sender, found the channel full: every `try_send` that was turned
+    /// away, plus every time a blocking send had to wait.
+    pub fn rejected_sends(&self) -> usize {
+        self.shared
+            .rejected
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared
+            .senders
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self
+            .shared
+            .senders
+            .fetch_sub(1, std::sync::atomic::Ordering::AcqRel)
+            == 1
+        {
+            parking::unpark_all(self.shared.recv_addr());
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Blocks while the channel is empty. Returns `Err` once every sender is gone or the channel
+    /// is closed, and the remaining messages have been received.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let shared = &*self.shared;
+        let _turn = shared.wait_for_turn();
+        loop {
+            //  check for senders first, so a message sent right before the last sender left is
+            //  still seen by the pop below
+            let disconnected = shared.is_disconnected(&shared.senders);
+            if let Some(value) = shared.pop() {
+                #[cfg(feature = "stats")]
+                shared.stats.received(1);
+                parking::unpark_one(shared.send_addr(), |_| {});
+                return Ok(value);
+            }
+            if disconnected {
+                return Err(shared.recv_error());
+            }
+            shared.wait_until_not_empty();
+        }
+    }
+
+    /// Blocks until there is at least one message, then moves up to `max` of the waiting ones
+    /// into `buf` in one go. Returns how many were received, or `Err` once every sender is gone
+    /// or the channel is closed, and the channel is empty.
+    pub fn recv_batch(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        let shared = &*self.shared;
+        if max == 0 {
+            return Ok(0);
+        }
+        let _turn = shared.wait_for_turn();
+        loop {
+            let disconnected = shared.is_disconnected(&shared.senders);
+            match shared.pop_batch(buf, max) {
+                0 => {}
+                received => {
+                    #[cfg(feature = "stats")]
+                    shared.stats.received(received);
+                    if received == 1 {
+                        parking::unpark_one(shared.send_addr(), |_| {});
+                    } else {
+                        parking::unpark_all(shared.send_addr());
+                    }
+                    return Ok(received);
+                }
+            }
+            if disconnected {
+                return Err(shared.recv_error());
+            }
+            shared.wait_until_not_empty();
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+
+    /// Same as `Sender::close`.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        self.shared.metrics()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared
+            .receivers
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self
+            .shared
+            .receivers
+            .fetch_sub(1, std::sync::atomic::Ordering::AcqRel)
+            == 1
+        {
+            //  senders may hold on to the channel for a long time, and anything buffered (a reply
+            //  handle waiting for a response, say) shouldn't stay alive with them
+            fence(std::sync::atomic::Ordering::SeqCst);
+            self.shared.drop_unreceived();
+            parking::unpark_all(self.shared.send_addr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_send_blocks_when_full() {
+        let (sender, receiver) = bounded(2);
+        let sent = Arc::new(AtomicBool::new(false));
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        let sending = {
+            let sent = Arc::clone(&sent);
+            thread::spawn(move || {
+                sender.send(3).unwrap();
+                sent.store(true, Ordering::Relaxed);
+            })
+        };
+        thread::sleep(Duration::from_millis(10));
+        assert!(!sent.load(Ordering::Relaxed));
+        assert_eq!(receiver.recv(), Ok(1));
+        sending.join().unwrap();
+        assert_eq!(receiver.recv(), Ok(2));
+        assert_eq!(receiver.recv(), Ok(3));
+        assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let (sender, receiver) = bounded(1);
+        sender.try_send(1).unwrap();
+        assert_eq!(sender.try_send(2), Err(TrySendError::Full(2)));
+        let start = Instant::now();
+        assert_eq!(
+            sender.send_timeout(3, Duration::from_millis(10)),
+            Err(SendTimeoutError::Timeout(3))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(sender.rejected_sends() >= 2);
+
+        let receiving = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            (receiver.recv(), receiver.recv())
+        });
+        sender.send_timeout(4, Duration::from_secs(10)).unwrap();
+        assert_eq!(receiving.join().unwrap(), (Ok(1), Ok(4)));
+        assert_eq!(
+            sender.send_timeout(5, Duration::from_secs(10)),
+            Err(SendTimeoutError::Disconnected(5))
+        );
+    }
+
+    #[test]
+    fn test_batches() {
+        let (sender, receiver) = bounded(4);
+        let receiving = thread::spawn(move || {
+            let mut received = vec![];
+            let mut batches = 0;
+            while let Ok(n) = receiver.recv_batch(&mut received, 3) {
+                assert!((1..=3).contains(&n));
+                batches += 1;
+            }
+            (received, batches)
+        });
+        sender.send_all(0..100).unwrap();
+        drop(sender);
+        let (received, batches) = receiving.join().unwrap();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+        assert!(batches >= 34);
+
+        let (sender, receiver) = bounded(2);
+        drop(receiver);
+        assert_eq!(
+            sender.send_all([1, 2, 3]),
+            Err(SendError::Disconnected(vec![1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn test_last_receiver_drops_buffered() {
+        let (sender, receiver) = bounded(4);
+        let message = Arc::new(());
+        sender.send(Arc::clone(&message)).unwrap();
+        sender
+            .send_all([Arc::clone(&message), Arc::clone(&message)])
+            .unwrap();
+        let receiver_clone = receiver.clone();
+        drop(receiver);
+        assert_eq!(Arc::strong_count(&message), 4);
+        //  the sender is still alive, but nobody can receive the messages any more
+        drop(receiver_clone);
+        assert_eq!(Arc::strong_count(&message), 1);
+        assert!(sender.send(Arc::clone(&message)).is_err());
+        assert_eq!(Arc::strong_count(&message), 1);
+    }
+
+    #[test]
+    fn test_send_all_streams() {
+        let (sender, receiver) = bounded(4);
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let sending = {
+            let pulled = Arc::clone(&pulled);
+            thread::spawn(move || {
+                let values = (0..1000).inspect(|_| {
+                    pulled.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                });
+                sender.send_all(values)
+            })
+        };
+        //  with nobody receiving, the sender fills the channel and blocks holding at most one
+        //  more channel's worth taken from the iterator
+        thread::sleep(Duration::from_millis(50));
+        assert!(pulled.load(std::sync::atomic::Ordering::Relaxed) <= 8);
+        let mut received = vec![];
+        while let Ok(value) = receiver.recv() {
+            received.push(value);
+        }
+        assert_eq!(sending.join().unwrap(), Ok(()));
+        assert_eq!(received, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_metrics() {
+        let (sender, receiver) = bounded(2);
+        sender.send_all([1, 2]).unwrap();
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.depth, metrics.sent, metrics.received), (2, 2, 0));
+
+        let sending = {
+            let sender = sender.clone();
+            thread::spawn(move || sender.send(3).unwrap())
+        };
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(receiver.recv(), Ok(1));
+        sending.join().unwrap();
+        assert!(sender.metrics().blocked_send_time > Duration::ZERO);
+
+        let mut buf = vec![];
+        receiver.recv_batch(&mut buf, 2).unwrap();
+        let receiving = thread::spawn(move || receiver.recv());
+        while sender.metrics().waiting_receivers == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        sender.send(4).unwrap();
+        assert_eq!(receiving.join().unwrap(), Ok(4));
+        assert_eq!(
+            sender.metrics(),
+            ChannelMetrics {
+                depth: 0,
+                sent: 4,
+                received: 4,
+                blocked_send_time: sender.metrics().blocked_send_time,
+                waiting_receivers: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_close() {
+        let (sender, receiver) = bounded::<i32>(4);
+        let waiting = {
+            let receiver = receiver.clone();
+            thread::spawn(move || receiver.recv())
+        };
+        thread::sleep(Duration::from_millis(10));
+        sender.close();
+        assert_eq!(waiting.join().unwrap(), Err(RecvError::Closed));
+
+        let (sender, receiver) = bounded(1);
+        sender.send(1).unwrap();
+        let blocked = {
+            let sender = sender.clone();
+            thread::spawn(move || sender.send(2))
+        };
+        thread::sleep(Duration::from_millis(10));
+        receiver.close();
+        assert_eq!(blocked.join().unwrap(), Err(SendError::Closed(2)));
+        assert!(sender.is_closed());
+        assert_eq!(sender.try_send(3), Err(TrySendError::Closed(3)));
+        assert_eq!(
+            sender.send_timeout(4, Duration::from_millis(10)),
+            Err(SendTimeoutError::Closed(4))
+        );
+        assert_eq!(sender.send_all([5, 6]), Err(SendError::Closed(vec![5, 6])));
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Err(RecvError::Closed));
+        let mut buf = vec![];
+        assert_eq!(receiver.recv_batch(&mut buf, 2), Err(RecvError::Closed));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (sender, receiver) = bounded::<i32>(1);
+        let receiving = thread::spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(10));
+        drop(sender);
+        assert_eq!(receiving.join().unwrap(), Err(RecvError::Disconnected));
+
+        let (sender, receiver) = bounded(1);
+        sender.send(1).unwrap();
+        let sending = thread::spawn(move || sender.send(2));
+        thread::sleep(Duration::from_millis(10));
+        drop(receiver);
+        assert_eq!(sending.join().unwrap(), Err(SendError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_mpmc() {
+        mpmc_round(WakeupPolicy::Throughput);
+        mpmc_round(WakeupPolicy::Fair);
+    }
+
+    fn mpmc_round(policy: WakeupPolicy) {
+        let (sender, receiver) = bounded_with_policy(4, policy);
+        let sender_slot = Mutex::new(Some(sender));
+        let producing = AtomicUsize::new(2);
+        let received = Arc::new(Mutex::new(vec![]));
+
+        //  even threads produce, odd threads consume until every sender is gone
+        let results = {
+            let received = Arc::clone(&received);
+            stress(4, 1_000).run(move |ctx| {
+                if ctx.thread % 2 == 0 {
+                    let sender = sender_slot.lock().unwrap().clone().unwrap();
+                    for i in 0..ctx.iterations {
+                        sender.send(ctx.thread * ctx.iterations + i).unwrap();
+                    }
+                    drop(sender);
+                    //  the last producer to finish drops the original sender as well
+                    if producing.fetch_sub(1, Ordering::AcqRel) == 1 {
+                        sender_slot.lock().unwrap().take();
+                    }
+                    ctx.thread
+                } else {
+                    let receiver = receiver.clone();
+                    while let Ok(value) = receiver.recv() {
+                        received.lock().unwrap().push(value);
+                    }
+                    ctx.thread
+                }
+            })
+        };
+        assert_eq!(results, vec![0, 1, 2, 3]);
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        let expected: Vec<_> = (0..1_000).chain(2_000..3_000).collect();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_fair_wakeup_order() {
+        let (sender, receiver) = bounded_with_policy(1, WakeupPolicy::Fair);
+        let handles: Vec<_> = (0..3)
+            .map(|i| {
+                let handle = {
+                    let receiver = receiver.clone();
+                    thread::spawn(move || receiver.recv())
+                };
+                //  let each receiver queue up before the next one arrives
+                while receiver.shared.next_ticket.load(Ordering::Relaxed) <= i {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                handle
+            })
+            .collect();
+        for value in 0..3 {
+            sender.send(value).unwrap();
+        }
+        let received: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(received, vec![Ok(0), Ok(1), Ok(2)]);
+    }
+}
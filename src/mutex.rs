@@ -1,63 +1,1891 @@
+#![allow(dead_code)]
+
 use std::{
-    cell::UnsafeCell,
+    cell::{Cell, UnsafeCell},
+    collections::VecDeque,
+    fmt,
+    marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
-    sync::atomic::AtomicBool,
+    panic::{RefUnwindSafe, UnwindSafe},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize},
+        Arc, LockResult, PoisonError, TryLockError, TryLockResult,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
-pub struct SpinLock<T> {
-    locked: AtomicBool,
+#[cfg(feature = "htm")]
+use crate::htm;
+#[cfg(not(loom))]
+use crate::parking;
+use crate::sync::{self, const_fn};
+
+//  everything about a SpinLock except its value, so that guards which no longer know the value's
+//  type (see `MappedLockGuard`) can still release the lock
+struct LockState {
+    locked: sync::atomic::AtomicBool,
+    poisoned: sync::atomic::AtomicBool,
+    #[cfg(feature = "lock-order")]
+    level: Option<(u32, &'static str)>,
+    #[cfg(feature = "lock-order")]
+    holder: lock_order::Holder,
+    #[cfg(feature = "stats")]
+    stats: stats::Counters,
+}
+
+impl LockState {
+    const_fn! {
+        fn new() -> Self {
+            Self {
+                locked: sync::atomic::AtomicBool::new(false),
+                poisoned: sync::atomic::AtomicBool::new(false),
+                #[cfg(feature = "lock-order")]
+                level: None,
+                #[cfg(feature = "lock-order")]
+                holder: lock_order::Holder::new(),
+                #[cfg(feature = "stats")]
+                stats: stats::Counters::new(),
+            }
+        }
+    }
+
+    fn unlock(&self, panicking: bool) {
+        #[cfg(feature = "lock-order")]
+        lock_order::released(self);
+        #[cfg(feature = "stats")]
+        self.stats.releasing();
+        if !panicking && thread::panicking() {
+            self.poisoned
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.locked
+            .store(false, std::sync::atomic::Ordering::Release);
+    }
+}
+
+pub struct SpinLock<T: ?Sized> {
+    state: LockState,
     value: UnsafeCell<T>,
 }
 
-unsafe impl<T> Send for SpinLock<T> where T: Send {}
-unsafe impl<T> Sync for SpinLock<T> {}
+unsafe impl<T: ?Sized> Send for SpinLock<T> where T: Send {}
+unsafe impl<T: ?Sized> Sync for SpinLock<T> where T: Send {}
+
+//  panics while the lock is held are surfaced through poisoning, like std's Mutex
+impl<T: ?Sized> UnwindSafe for SpinLock<T> {}
+impl<T: ?Sized> RefUnwindSafe for SpinLock<T> {}
 
 impl<T> SpinLock<T> {
-    pub fn new(value: T) -> Self {
-        Self {
-            locked: AtomicBool::new(false),
-            value: UnsafeCell::new(value),
+    const_fn! {
+        pub fn new(value: T) -> Self {
+            Self {
+                state: LockState::new(),
+                value: UnsafeCell::new(value),
+            }
         }
     }
 
-    pub fn lock(&self) -> LockGuard<T> {
+    const_fn! {
+        /// Creates a lock that takes part in lock-order checking when the `lock-order` feature
+        /// is enabled: a thread may only `lock` it while every lock it already holds has a lower
+        /// level. Without the feature the level and name are ignored.
+        pub fn with_level(value: T, level: u32, name: &'static str) -> Self {
+            #[cfg(not(feature = "lock-order"))]
+            let _ = (level, name);
+            #[allow(unused_mut)]
+            let mut lock = Self::new(value);
+            #[cfg(feature = "lock-order")]
+            {
+                lock.state.level = Some((level, name));
+            }
+            lock
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let value = self.value.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Returns `Err` if a previous holder panicked; the error still carries the guard, so the
+    /// data can be inspected or repaired through `PoisonError::into_inner`.
+    pub fn lock(&self) -> LockResult<LockGuard<'_, T>> {
+        #[cfg(feature = "lock-order")]
+        if let Some((level, name)) = self.state.level {
+            lock_order::check(level, name);
+        }
+        let mut spins = 0;
         while self
+            .state
+            .locked
+            .compare_exchange_weak(
+                false,
+                true,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            //  wait with plain loads so waiters share the cache line instead of bouncing it with
+            //  failed compare-exchanges (test-and-test-and-set)
+            spins += 1;
+            while self.is_locked() {
+                spins += 1;
+                sync::hint::spin_loop();
+            }
+        }
+        self.guard(spins)
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<LockGuard<'_, T>> {
+        if self
+            .state
             .locked
             .compare_exchange(
                 false,
                 true,
-                std::sync::atomic::Ordering::AcqRel,
+                std::sync::atomic::Ordering::Acquire,
                 std::sync::atomic::Ordering::Relaxed,
             )
             .is_err()
         {
-            std::hint::spin_loop();
+            return Err(TryLockError::WouldBlock);
+        }
+        Ok(self.guard(0)?)
+    }
+
+    pub fn lock_timeout(&self, dur: Duration) -> TryLockResult<LockGuard<'_, T>> {
+        let deadline = Instant::now() + dur;
+        loop {
+            match self.try_lock() {
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {}
+                result => return result,
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Locks two locks at once, always taking them in address order so that two threads calling
+    /// `lock_both(a, b)` and `lock_both(b, a)` cannot deadlock each other.
+    pub fn lock_both<'a, U: ?Sized>(
+        a: &'a Self,
+        b: &'a SpinLock<U>,
+    ) -> LockResult<(LockGuard<'a, T>, LockGuard<'a, U>)> {
+        let (addr_a, addr_b) = (a.addr(), b.addr());
+        assert_ne!(addr_a, addr_b, "cannot lock the same lock twice");
+        let (guard_a, guard_b) = if addr_a < addr_b {
+            let guard_a = a.lock();
+            (guard_a, b.lock())
+        } else {
+            let guard_b = b.lock();
+            (a.lock(), guard_b)
+        };
+        match (guard_a, guard_b) {
+            (Ok(guard_a), Ok(guard_b)) => Ok((guard_a, guard_b)),
+            (guard_a, guard_b) => Err(PoisonError::new((
+                guard_a.unwrap_or_else(PoisonError::into_inner),
+                guard_b.unwrap_or_else(PoisonError::into_inner),
+            ))),
+        }
+    }
+
+    /// Locks every lock in `locks` in address order. The guards are returned in the same order
+    /// as `locks`.
+    pub fn lock_all<'a>(locks: &[&'a Self]) -> LockResult<Vec<LockGuard<'a, T>>> {
+        let mut order: Vec<usize> = (0..locks.len()).collect();
+        order.sort_by_key(|&i| locks[i].addr());
+        assert!(
+            order
+                .windows(2)
+                .all(|pair| locks[pair[0]].addr() != locks[pair[1]].addr()),
+            "cannot lock the same lock twice"
+        );
+        let mut guards: Vec<Option<LockResult<LockGuard<'a, T>>>> =
+            locks.iter().map(|_| None).collect();
+        for i in order {
+            guards[i] = Some(locks[i].lock());
+        }
+        let mut poisoned = false;
+        let guards = guards
+            .into_iter()
+            .map(|guard| {
+                guard.unwrap().unwrap_or_else(|err| {
+                    poisoned = true;
+                    err.into_inner()
+                })
+            })
+            .collect();
+        if poisoned {
+            Err(PoisonError::new(guards))
+        } else {
+            Ok(guards)
+        }
+    }
+
+    /// Like `lock`, but the guard keeps its own clone of the `Arc` instead of borrowing the lock,
+    /// so it can be returned from functions or moved to other threads.
+    pub fn lock_arc(self: &Arc<Self>) -> LockResult<ArcLockGuard<T>> {
+        let (guard, poisoned) = match self.lock() {
+            Ok(guard) => (guard, false),
+            Err(err) => (err.into_inner(), true),
+        };
+        let panicking = guard.panicking;
+        mem::forget(guard);
+        let guard = ArcLockGuard {
+            lock: Arc::clone(self),
+            panicking,
+            _not_sync: PhantomData,
+        };
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Acquires the lock without a guard, for code such as C callbacks where the lock is taken in
+    /// one call and released in another. Raw locking ignores poisoning; the data is reached
+    /// through `data_ptr` and the lock must be released with `raw_unlock`.
+    /// With the `lock-order` feature, the lock counts as held by the locking thread until that
+    /// thread calls `raw_unlock`.
+    pub fn raw_lock(&self) {
+        mem::forget(self.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    /// Like `raw_lock`, but returns false instead of spinning if the lock is held.
+    pub fn raw_try_lock(&self) -> bool {
+        match self.try_lock() {
+            Ok(guard) => mem::forget(guard),
+            Err(TryLockError::Poisoned(err)) => mem::forget(err.into_inner()),
+            Err(TryLockError::WouldBlock) => return false,
+        }
+        true
+    }
+
+    /// Releases a lock taken with `raw_lock` or `raw_try_lock`.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held through a raw acquisition that has not been released yet, and no
+    /// reference obtained through `data_ptr` may be used after this call. It may be called from
+    /// a different thread than the one that locked.
+    pub unsafe fn raw_unlock(&self) {
+        self.state.unlock(true);
+    }
+
+    /// A pointer to the protected value. Dereferencing it is only sound while the lock is held.
+    pub fn data_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    /// Runs `f` inside a hardware transaction instead of taking the lock when the CPU supports
+    /// RTM, so threads whose critical sections touch different data don't serialise. After
+    /// repeated aborts, or without RTM, the lock is taken as usual. `f` may run more than once
+    /// but only one run takes effect. Elided runs are not counted by `stats` or checked by
+    /// `lock-order`.
+    #[cfg(feature = "htm")]
+    pub fn lock_elided<R>(&self, f: impl FnOnce(&mut T) -> R) -> LockResult<R> {
+        if htm::available() && !self.is_poisoned() {
+            for _ in 0..ELISION_ATTEMPTS {
+                let status = unsafe { htm::begin() };
+                if status == htm::STARTED {
+                    //  reading the lock word puts it in the transaction's read set, so a thread
+                    //  that takes the lock for real aborts us
+                    if self.is_locked() {
+                        unsafe { htm::abort() };
+                    }
+                    let result = f(unsafe { &mut *self.value.get() });
+                    unsafe { htm::end() };
+                    return Ok(result);
+                }
+                if status & htm::ABORT_EXPLICIT != 0 && htm::abort_code(status) == htm::ABORT_CODE {
+                    while self.is_locked() {
+                        sync::hint::spin_loop();
+                    }
+                } else if status & htm::ABORT_RETRY == 0 {
+                    break;
+                }
+            }
+        }
+        match self.lock() {
+            Ok(mut guard) => Ok(f(&mut guard)),
+            Err(err) => Err(PoisonError::new(f(&mut err.into_inner()))),
+        }
+    }
+
+    fn addr(&self) -> usize {
+        &self.state as *const LockState as usize
+    }
+
+    /// Only a snapshot: the lock may have been taken or released by the time this returns.
+    pub fn is_locked(&self) -> bool {
+        self.state.locked.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.state
+            .poisoned
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn unpoison(&self) {
+        self.state
+            .poisoned
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> LockStats {
+        self.state.stats.snapshot()
+    }
+
+    fn guard(&self, spins: u64) -> LockResult<LockGuard<'_, T>> {
+        #[cfg(not(feature = "stats"))]
+        let _ = spins;
+        #[cfg(feature = "stats")]
+        self.state.stats.acquired(spins);
+        #[cfg(feature = "lock-order")]
+        if let Some((level, name)) = self.state.level {
+            lock_order::acquired(&self.state, level, name);
+        }
+        let guard = LockGuard {
+            lock: self,
+            panicking: thread::panicking(),
+            _not_sync: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
         }
-        LockGuard { lock: &self }
     }
 }
 
-pub struct LockGuard<'a, T> {
+#[cfg(feature = "htm")]
+const ELISION_ATTEMPTS: usize = 3;
+
+pub struct LockGuard<'a, T: ?Sized> {
     lock: &'a SpinLock<T>,
+    //  a guard taken while already unwinding must not poison the lock when it is dropped
+    panicking: bool,
+    //  `&SpinLock<T>` is Sync for any `T: Send`, but sharing the guard hands out `&T`
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: ?Sized> Sync for LockGuard<'_, T> where T: Sync {}
+
+impl<T: ?Sized> Deref for LockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for LockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for LockGuard<'_, T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T: ?Sized> LockGuard<'a, T> {
+    /// Projects the guard onto a part of the locked data, keeping the lock held until the
+    /// returned guard is dropped.
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> MappedLockGuard<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        //  if `f` panics `orig` is still live and unlocks (and poisons) as usual
+        let value = f(unsafe { &mut *orig.lock.value.get() }) as *mut U;
+        Self::into_mapped(orig, value)
+    }
+
+    pub fn try_map<U: ?Sized, F>(orig: Self, f: F) -> Result<MappedLockGuard<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        match f(unsafe { &mut *orig.lock.value.get() }) {
+            Some(value) => {
+                let value = value as *mut U;
+                Ok(Self::into_mapped(orig, value))
+            }
+            None => Err(orig),
+        }
+    }
+
+    fn into_mapped<U: ?Sized>(orig: Self, value: *mut U) -> MappedLockGuard<'a, U> {
+        let mapped = MappedLockGuard {
+            state: &orig.lock.state,
+            panicking: orig.panicking,
+            value,
+            _marker: PhantomData,
+        };
+        mem::forget(orig);
+        mapped
+    }
+}
+
+impl<T: ?Sized> Drop for LockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.unlock(self.panicking);
+    }
+}
+
+pub struct ArcLockGuard<T: ?Sized> {
+    lock: Arc<SpinLock<T>>,
+    panicking: bool,
+    //  `Arc<SpinLock<T>>` is Sync for any `T: Send`, but sharing the guard hands out `&T`
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: ?Sized> Sync for ArcLockGuard<T> where T: Sync {}
+
+impl<T: ?Sized> Deref for ArcLockGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for ArcLockGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for ArcLockGuard<T> {
+    fn drop(&mut self) {
+        self.lock.state.unlock(self.panicking);
+    }
+}
+
+pub struct MappedLockGuard<'a, U: ?Sized> {
+    state: &'a LockState,
+    panicking: bool,
+    value: *mut U,
+    _marker: PhantomData<&'a mut U>,
+}
+
+unsafe impl<U: ?Sized> Sync for MappedLockGuard<'_, U> where U: Sync {}
+
+impl<U: ?Sized> Deref for MappedLockGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.value }
+    }
+}
+
+impl<U: ?Sized> DerefMut for MappedLockGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<U: ?Sized> fmt::Debug for MappedLockGuard<'_, U>
+where
+    U: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<U: ?Sized> Drop for MappedLockGuard<'_, U> {
+    fn drop(&mut self) {
+        self.state.unlock(self.panicking);
+    }
+}
+
+pub struct TicketLock<T> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for TicketLock<T> where T: Send {}
+unsafe impl<T> Sync for TicketLock<T> where T: Send {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        //  threads are served in the order they took their ticket
+        let ticket = self
+            .next_ticket
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        loop {
+            let serving = self.now_serving.load(std::sync::atomic::Ordering::Acquire);
+            if serving == ticket {
+                break;
+            }
+            //  only the next thread in line can get the lock, so the ones behind it give up the
+            //  CPU rather than keep the holder from running when there are more threads than cores
+            if ticket.wrapping_sub(serving) > 1 {
+                std::thread::yield_now();
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        TicketLockGuard {
+            lock: self,
+            _not_sync: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        //  only take a ticket if it would be served immediately. The CAS is on a different atomic
+        //  than the one the previous holder released, so the acquire has to be on this load.
+        let serving = self.now_serving.load(std::sync::atomic::Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| TicketLockGuard {
+                lock: self,
+                _not_sync: PhantomData,
+            })
+    }
 }
 
-impl<T> Deref for LockGuard<'_, T> {
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+    //  `&TicketLock<T>` is Sync for any `T: Send`, but sharing the guard hands out `&T`
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T> Sync for TicketLockGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         unsafe { &*self.lock.value.get() }
     }
 }
 
-impl<T> DerefMut for LockGuard<'_, T> {
+impl<T> DerefMut for TicketLockGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { &mut *self.lock.value.get() }
     }
 }
 
-impl<T> Drop for LockGuard<'_, T> {
+impl<T> Drop for TicketLockGuard<'_, T> {
     fn drop(&mut self) {
         self.lock
-            .locked
-            .store(false, std::sync::atomic::Ordering::Release);
+            .now_serving
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+    }
+}
+
+#[cfg(feature = "lock-order")]
+mod lock_order {
+    use std::{
+        cell::UnsafeCell,
+        sync::{Arc, Mutex, PoisonError},
+    };
+
+    use super::LockState;
+
+    struct HeldLock {
+        addr: usize,
+        level: u32,
+        name: &'static str,
+    }
+
+    type HeldLocks = Arc<Mutex<Vec<HeldLock>>>;
+
+    thread_local! {
+        //  behind a mutex so that a guard sent to another thread can still remove its entry
+        static HELD: HeldLocks = Arc::new(Mutex::new(Vec::new()));
+    }
+
+    //  the list of the thread that took the lock, so the release finds the entry wherever it
+    //  happens. Only accessed by the current holder of the lock
+    pub(super) struct Holder(UnsafeCell<Option<HeldLocks>>);
+
+    impl Holder {
+        pub(super) const fn new() -> Self {
+            Self(UnsafeCell::new(None))
+        }
+    }
+
+    pub(super) fn check(level: u32, name: &'static str) {
+        let highest = HELD.with(|held| {
+            let held = held.lock().unwrap_or_else(PoisonError::into_inner);
+            held.iter()
+                .max_by_key(|lock| lock.level)
+                .map(|lock| (lock.level, lock.name))
+        });
+        if let Some((highest_level, highest_name)) = highest {
+            if level <= highest_level {
+                panic!(
+                    "lock order violation: acquiring `{}` (level {}) while holding `{}` (level {})",
+                    name, level, highest_name, highest_level
+                );
+            }
+        }
+    }
+
+    pub(super) fn acquired(state: &LockState, level: u32, name: &'static str) {
+        let addr = state as *const LockState as usize;
+        HELD.with(|held| {
+            held.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(HeldLock { addr, level, name });
+            unsafe { *state.holder.0.get() = Some(Arc::clone(held)) };
+        });
+    }
+
+    pub(super) fn released(state: &LockState) {
+        let addr = state as *const LockState as usize;
+        if let Some(held) = unsafe { (*state.holder.0.get()).take() } {
+            let mut held = held.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(pos) = held.iter().rposition(|lock| lock.addr == addr) {
+                held.remove(pos);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    pub acquisitions: u64,
+    pub contended_acquisitions: u64,
+    pub spin_iterations: u64,
+    pub max_hold_time: Duration,
+}
+
+#[cfg(feature = "stats")]
+mod stats {
+    use std::{
+        cell::UnsafeCell,
+        sync::atomic::{AtomicU64, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use super::LockStats;
+
+    pub(super) struct Counters {
+        acquisitions: AtomicU64,
+        contended_acquisitions: AtomicU64,
+        spin_iterations: AtomicU64,
+        max_hold_nanos: AtomicU64,
+        //  only accessed by the current holder of the lock
+        hold_start: UnsafeCell<Option<Instant>>,
+    }
+
+    impl Counters {
+        pub(super) const fn new() -> Self {
+            Self {
+                acquisitions: AtomicU64::new(0),
+                contended_acquisitions: AtomicU64::new(0),
+                spin_iterations: AtomicU64::new(0),
+                max_hold_nanos: AtomicU64::new(0),
+                hold_start: UnsafeCell::new(None),
+            }
+        }
+
+        pub(super) fn acquired(&self, spins: u64) {
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+            if spins > 0 {
+                self.contended_acquisitions.fetch_add(1, Ordering::Relaxed);
+                self.spin_iterations.fetch_add(spins, Ordering::Relaxed);
+            }
+            unsafe { *self.hold_start.get() = Some(Instant::now()) };
+        }
+
+        pub(super) fn releasing(&self) {
+            if let Some(start) = unsafe { (*self.hold_start.get()).take() } {
+                let held = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+                self.max_hold_nanos.fetch_max(held, Ordering::Relaxed);
+            }
+        }
+
+        pub(super) fn snapshot(&self) -> LockStats {
+            LockStats {
+                acquisitions: self.acquisitions.load(Ordering::Relaxed),
+                contended_acquisitions: self.contended_acquisitions.load(Ordering::Relaxed),
+                spin_iterations: self.spin_iterations.load(Ordering::Relaxed),
+                max_hold_time: Duration::from_nanos(self.max_hold_nanos.load(Ordering::Relaxed)),
+            }
+        }
+    }
+}
+
+const MCS_SPIN_LIMIT: usize = 100;
+
+struct McsNode {
+    next: AtomicPtr<McsNode>,
+    locked: AtomicBool,
+}
+
+pub struct McsLock<T> {
+    tail: AtomicPtr<McsNode>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for McsLock<T> where T: Send {}
+unsafe impl<T> Sync for McsLock<T> where T: Send {}
+
+impl<T> McsLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> McsLockGuard<'_, T> {
+        //  the node is heap allocated so it stays put while the guard moves around
+        let node = Box::into_raw(Box::new(McsNode {
+            next: AtomicPtr::new(ptr::null_mut()),
+            locked: AtomicBool::new(true),
+        }));
+        let prev = self.tail.swap(node, std::sync::atomic::Ordering::AcqRel);
+        if !prev.is_null() {
+            //  queue up behind the previous holder and spin on our own node only
+            unsafe {
+                (*prev)
+                    .next
+                    .store(node, std::sync::atomic::Ordering::Release);
+                let mut spins = 0;
+                while (*node).locked.load(std::sync::atomic::Ordering::Acquire) {
+                    //  with more threads than cores the holder may be the one waiting to run, so
+                    //  after a while hand over the CPU rather than burn the rest of the time slice
+                    if spins < MCS_SPIN_LIMIT {
+                        spins += 1;
+                        std::hint::spin_loop();
+                    } else {
+                        std::thread::yield_now();
+                    }
+                }
+            }
+        }
+        McsLockGuard { lock: self, node }
+    }
+}
+
+pub struct McsLockGuard<'a, T> {
+    lock: &'a McsLock<T>,
+    node: *mut McsNode,
+}
+
+impl<T> Deref for McsLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for McsLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for McsLockGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut next = (*self.node).next.load(std::sync::atomic::Ordering::Acquire);
+            if next.is_null() {
+                //  no known successor, so try to mark the queue as empty
+                if self
+                    .lock
+                    .tail
+                    .compare_exchange(
+                        self.node,
+                        ptr::null_mut(),
+                        std::sync::atomic::Ordering::Release,
+                        std::sync::atomic::Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    drop(Box::from_raw(self.node));
+                    return;
+                }
+                //  a successor swapped itself in but has not linked to us yet
+                loop {
+                    next = (*self.node).next.load(std::sync::atomic::Ordering::Acquire);
+                    if !next.is_null() {
+                        break;
+                    }
+                    std::hint::spin_loop();
+                }
+            }
+            (*next)
+                .locked
+                .store(false, std::sync::atomic::Ordering::Release);
+            drop(Box::from_raw(self.node));
+        }
+    }
+}
+
+const UNLOCKED: u8 = 0;
+const LOCKED: u8 = 1;
+const CONTENDED: u8 = 2;
+
+//  a parked thread plus a flag set by whoever wakes it, so a spurious unpark can be told apart
+//  from a real one
+struct Waiter {
+    thread: Thread,
+    notified: AtomicBool,
+}
+
+impl Waiter {
+    fn new() -> Self {
+        Self {
+            thread: thread::current(),
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    fn park(&self) {
+        while !self.notified.load(std::sync::atomic::Ordering::Acquire) {
+            thread::park();
+        }
+    }
+
+    fn wake(&self) {
+        self.notified
+            .store(true, std::sync::atomic::Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
+//  the blocking half shared by Mutex and AdaptiveMutex: a three-state lock word plus a queue of
+//  parked threads
+struct ParkingState {
+    state: AtomicU8,
+    waiters: SpinLock<VecDeque<Arc<Waiter>>>,
+}
+
+impl ParkingState {
+    const_fn! {
+        fn new() -> Self {
+            Self {
+                state: AtomicU8::new(UNLOCKED),
+                waiters: SpinLock::new(VecDeque::new()),
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(
+                UNLOCKED,
+                LOCKED,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    fn lock_contended(&self) {
+        loop {
+            //  a lock taken on this path is marked contended so its unlock wakes the next waiter
+            if self
+                .state
+                .swap(CONTENDED, std::sync::atomic::Ordering::Acquire)
+                == UNLOCKED
+            {
+                return;
+            }
+            //  a fresh entry each round: the previous one was popped by the unlock that woke us
+            let waiter = Arc::new(Waiter::new());
+            {
+                let mut waiters = self.waiters.lock().unwrap();
+                //  the holder may have unlocked before we queued, in which case nobody would wake us
+                if self.state.load(std::sync::atomic::Ordering::Relaxed) != CONTENDED {
+                    continue;
+                }
+                waiters.push_back(Arc::clone(&waiter));
+            }
+            //  only an unlock that popped our entry ends the wait, not a stray unpark
+            waiter.park();
+        }
+    }
+
+    fn unlock(&self) {
+        if self
+            .state
+            .swap(UNLOCKED, std::sync::atomic::Ordering::Release)
+            == CONTENDED
+        {
+            if let Some(waiter) = self.waiters.lock().unwrap().pop_front() {
+                waiter.wake();
+            }
+        }
+    }
+}
+
+pub struct Mutex<T> {
+    parking: ParkingState,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for Mutex<T> where T: Send {}
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    const_fn! {
+        pub fn new(value: T) -> Self {
+            Self {
+                parking: ParkingState::new(),
+                value: UnsafeCell::new(value),
+            }
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if !self.parking.try_lock() {
+            self.parking.lock_contended();
+        }
+        MutexGuard {
+            lock: self,
+            _not_sync: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.parking.try_lock() {
+            Some(MutexGuard {
+                lock: self,
+                _not_sync: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+    //  `&Mutex<T>` is Sync for any `T: Send`, but sharing the guard hands out `&T`
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T> Sync for MutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.parking.unlock();
+    }
+}
+
+const ADAPTIVE_SPIN_LIMIT: usize = 100;
+
+pub struct AdaptiveMutex<T> {
+    parking: ParkingState,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for AdaptiveMutex<T> where T: Send {}
+unsafe impl<T> Sync for AdaptiveMutex<T> where T: Send {}
+
+impl<T> AdaptiveMutex<T> {
+    const_fn! {
+        pub fn new(value: T) -> Self {
+            Self {
+                parking: ParkingState::new(),
+                value: UnsafeCell::new(value),
+            }
+        }
+    }
+
+    pub fn lock(&self) -> AdaptiveMutexGuard<'_, T> {
+        //  short critical sections are usually over before it is worth parking
+        for _ in 0..ADAPTIVE_SPIN_LIMIT {
+            if self
+                .parking
+                .state
+                .load(std::sync::atomic::Ordering::Relaxed)
+                == UNLOCKED
+                && self.parking.try_lock()
+            {
+                return AdaptiveMutexGuard {
+                    lock: self,
+                    _not_sync: PhantomData,
+                };
+            }
+            std::hint::spin_loop();
+        }
+        self.parking.lock_contended();
+        AdaptiveMutexGuard {
+            lock: self,
+            _not_sync: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<AdaptiveMutexGuard<'_, T>> {
+        if self.parking.try_lock() {
+            Some(AdaptiveMutexGuard {
+                lock: self,
+                _not_sync: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct AdaptiveMutexGuard<'a, T> {
+    lock: &'a AdaptiveMutex<T>,
+    //  `&AdaptiveMutex<T>` is Sync for any `T: Send`, but sharing the guard hands out `&T`
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T> Sync for AdaptiveMutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for AdaptiveMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for AdaptiveMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for AdaptiveMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.parking.unlock();
+    }
+}
+
+#[cfg(not(loom))]
+const PARKED: u8 = 2;
+
+//  a one-byte lock whose waiters live in the global parking table, keyed by the lock's address
+#[cfg(not(loom))]
+pub struct ParkingMutex<T> {
+    state: AtomicU8,
+    value: UnsafeCell<T>,
+}
+
+#[cfg(not(loom))]
+unsafe impl<T> Send for ParkingMutex<T> where T: Send {}
+#[cfg(not(loom))]
+unsafe impl<T> Sync for ParkingMutex<T> where T: Send {}
+
+#[cfg(not(loom))]
+impl<T> ParkingMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU8::new(UNLOCKED),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> ParkingMutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange_weak(
+                UNLOCKED,
+                LOCKED,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            self.lock_slow();
+        }
+        ParkingMutexGuard {
+            lock: self,
+            _not_sync: PhantomData,
+        }
+    }
+
+    fn lock_slow(&self) {
+        let mut state = self.state.load(std::sync::atomic::Ordering::Relaxed);
+        loop {
+            if state & LOCKED == 0 {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state | LOCKED,
+                    std::sync::atomic::Ordering::Acquire,
+                    std::sync::atomic::Ordering::Relaxed,
+                ) {
+                    Ok(_) => return,
+                    Err(current) => state = current,
+                }
+                continue;
+            }
+            //  tell the holder it has to go through the parking table when it unlocks
+            if state & PARKED == 0 {
+                if let Err(current) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED,
+                    std::sync::atomic::Ordering::Relaxed,
+                    std::sync::atomic::Ordering::Relaxed,
+                ) {
+                    state = current;
+                    continue;
+                }
+            }
+            parking::park(self.addr(), || {
+                self.state.load(std::sync::atomic::Ordering::Relaxed) == LOCKED | PARKED
+            });
+            state = self.state.load(std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<ParkingMutexGuard<'_, T>> {
+        let state = self.state.load(std::sync::atomic::Ordering::Relaxed);
+        if state & LOCKED != 0 {
+            return None;
+        }
+        match self.state.compare_exchange(
+            state,
+            state | LOCKED,
+            std::sync::atomic::Ordering::Acquire,
+            std::sync::atomic::Ordering::Relaxed,
+        ) {
+            Ok(_) => Some(ParkingMutexGuard {
+                lock: self,
+                _not_sync: PhantomData,
+            }),
+            Err(_) => None,
+        }
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn unlock(&self) {
+        if self
+            .state
+            .compare_exchange(
+                LOCKED,
+                UNLOCKED,
+                std::sync::atomic::Ordering::Release,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            return;
+        }
+        //  the state is updated while the bucket is locked, so a thread about to park either sees
+        //  the new state in its validation or is already queued and gets woken
+        parking::unpark_one(self.addr(), |result| {
+            let state = if result.have_more_waiters {
+                PARKED
+            } else {
+                UNLOCKED
+            };
+            self.state
+                .store(state, std::sync::atomic::Ordering::Release);
+        });
+    }
+}
+
+#[cfg(not(loom))]
+pub struct ParkingMutexGuard<'a, T> {
+    lock: &'a ParkingMutex<T>,
+    //  `&ParkingMutex<T>` is Sync for any `T: Send`, but sharing the guard hands out `&T`
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+#[cfg(not(loom))]
+unsafe impl<T> Sync for ParkingMutexGuard<'_, T> where T: Sync {}
+
+#[cfg(not(loom))]
+impl<T> Deref for ParkingMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<T> DerefMut for ParkingMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<T> Drop for ParkingMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+//  the address of a thread local is unique among live threads and never zero
+fn current_thread_id() -> usize {
+    thread_local! {
+        static ID: u8 = const { 0 };
+    }
+    ID.with(|id| id as *const u8 as usize)
+}
+
+pub struct ReentrantLock<T> {
+    owner: AtomicUsize,
+    //  only ever touched by the owning thread
+    count: UnsafeCell<usize>,
+    value: T,
+}
+
+unsafe impl<T> Send for ReentrantLock<T> where T: Send {}
+unsafe impl<T> Sync for ReentrantLock<T> where T: Send {}
+
+impl<T> ReentrantLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            owner: AtomicUsize::new(0),
+            count: UnsafeCell::new(0),
+            value,
+        }
+    }
+
+    pub fn lock(&self) -> ReentrantLockGuard<'_, T> {
+        let this_thread = current_thread_id();
+        if self.owner.load(std::sync::atomic::Ordering::Relaxed) == this_thread {
+            unsafe {
+                *self.count.get() = (*self.count.get())
+                    .checked_add(1)
+                    .expect("lock count overflow in reentrant lock");
+            }
+        } else {
+            while self
+                .owner
+                .compare_exchange(
+                    0,
+                    this_thread,
+                    std::sync::atomic::Ordering::Acquire,
+                    std::sync::atomic::Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                std::hint::spin_loop();
+            }
+            unsafe { *self.count.get() = 1 };
+        }
+        ReentrantLockGuard {
+            lock: self,
+            _no_send: PhantomData,
+        }
+    }
+}
+
+pub struct ReentrantLockGuard<'a, T> {
+    lock: &'a ReentrantLock<T>,
+    //  the lock is owned by a thread, so the guard has to stay on it
+    _no_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for ReentrantLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.lock.value
+    }
+}
+
+impl<T> Drop for ReentrantLockGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            *self.lock.count.get() -= 1;
+            if *self.lock.count.get() == 0 {
+                self.lock
+                    .owner
+                    .store(0, std::sync::atomic::Ordering::Release);
+            }
+        }
+    }
+}
+
+pub struct SpinCondvar {
+    waiters: SpinLock<VecDeque<Arc<Waiter>>>,
+}
+
+impl Default for SpinCondvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpinCondvar {
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                waiters: SpinLock::new(VecDeque::new()),
+            }
+        }
+    }
+
+    /// Releases the lock held by `guard`, blocks until notified and then re-acquires the lock.
+    pub fn wait<'a, T: ?Sized>(&self, guard: LockGuard<'a, T>) -> LockResult<LockGuard<'a, T>> {
+        let lock = guard.lock;
+        let waiter = Arc::new(Waiter::new());
+        //  queue up before unlocking so a notify issued right after the unlock can't be missed
+        self.waiters.lock().unwrap().push_back(Arc::clone(&waiter));
+        drop(guard);
+        waiter.park();
+        lock.lock()
+    }
+
+    pub fn notify_one(&self) {
+        if let Some(waiter) = self.waiters.lock().unwrap().pop_front() {
+            waiter.wake();
+        }
+    }
+
+    pub fn notify_all(&self) {
+        let waiters = mem::take(&mut *self.waiters.lock().unwrap());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_try_lock() {
+        let spin_lock = SpinLock::new(0);
+        let mut guard = spin_lock.try_lock().unwrap();
+        *guard += 1;
+        assert!(matches!(
+            spin_lock.try_lock(),
+            Err(TryLockError::WouldBlock)
+        ));
+        drop(guard);
+        assert!(!spin_lock.is_locked());
+        assert_eq!(*spin_lock.try_lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let spin_lock = SpinLock::new(0);
+        let guard = spin_lock.lock().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let start = Instant::now();
+                assert!(spin_lock.lock_timeout(Duration::from_millis(20)).is_err());
+                assert!(start.elapsed() >= Duration::from_millis(20));
+            });
+        });
+        drop(guard);
+        assert!(spin_lock.lock_timeout(Duration::from_millis(20)).is_ok());
+    }
+
+    #[test]
+    fn test_unsized_spin_lock() {
+        let callbacks: Vec<Box<SpinLock<dyn FnMut() -> usize + Send>>> = vec![
+            Box::new(SpinLock::new({
+                let mut calls = 0;
+                move || {
+                    calls += 1;
+                    calls
+                }
+            })),
+            Box::new(SpinLock::new(|| 10)),
+        ];
+        for callback in &callbacks {
+            (callback.lock().unwrap())();
+        }
+        assert_eq!((callbacks[0].lock().unwrap())(), 2);
+        assert_eq!((callbacks[1].lock().unwrap())(), 10);
+
+        let slice: Arc<SpinLock<[i32]>> = Arc::new(SpinLock::new([1, 2, 3]));
+        slice.lock().unwrap()[1] = 5;
+        assert_eq!(&*slice.lock_arc().unwrap(), &[1, 5, 3]);
+    }
+
+    #[cfg(feature = "htm")]
+    #[test]
+    fn test_lock_elided() {
+        let spin_lock = Arc::new(SpinLock::new(0));
+        let lock_clone = Arc::clone(&spin_lock);
+        stress(4, 1000).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                lock_clone.lock_elided(|value| *value += 1).unwrap();
+            }
+        });
+        assert_eq!(*spin_lock.lock().unwrap(), 4000);
+
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = spin_lock.lock().unwrap();
+            panic!("poison the lock");
+        });
+        assert!(spin_lock.lock_elided(|value| *value).is_err());
+    }
+
+    #[test]
+    fn test_raw_lock() {
+        let spin_lock = SpinLock::new(0);
+        spin_lock.raw_lock();
+        assert!(spin_lock.is_locked());
+        assert!(!spin_lock.raw_try_lock());
+        std::thread::scope(|s| {
+            //  released on another thread, the way a C library calling back into us might
+            s.spawn(|| unsafe {
+                *spin_lock.data_ptr() = 5;
+                spin_lock.raw_unlock();
+            });
+        });
+        assert!(!spin_lock.is_locked());
+        assert!(spin_lock.raw_try_lock());
+        unsafe { spin_lock.raw_unlock() };
+        assert_eq!(*spin_lock.lock().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_poisoning() {
+        let spin_lock = SpinLock::new(0);
+        let result = std::panic::catch_unwind(|| {
+            let mut guard = spin_lock.lock().unwrap();
+            *guard = 1;
+            panic!("poison the lock");
+        });
+        assert!(result.is_err());
+        assert!(spin_lock.is_poisoned());
+        assert_eq!(*spin_lock.lock().unwrap_err().into_inner(), 1);
+        assert!(matches!(
+            spin_lock.try_lock(),
+            Err(TryLockError::Poisoned(_))
+        ));
+
+        spin_lock.unpoison();
+        assert_eq!(*spin_lock.lock().unwrap(), 1);
+        assert_eq!(spin_lock.into_inner().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_map_guard() {
+        let spin_lock = SpinLock::new((1, vec![2, 3]));
+        let mut second = LockGuard::map(spin_lock.lock().unwrap(), |(_, v)| v);
+        second.push(4);
+        assert!(spin_lock.try_lock().is_err());
+        drop(second);
+
+        let guard = spin_lock.lock().unwrap();
+        let guard = LockGuard::try_map(guard, |(_, v)| v.get_mut(10)).unwrap_err();
+        let mut third = LockGuard::try_map(guard, |(_, v)| v.get_mut(2)).unwrap();
+        *third += 1;
+        drop(third);
+        assert_eq!(*spin_lock.lock().unwrap(), (1, vec![2, 3, 5]));
+    }
+
+    #[cfg(feature = "lock-order")]
+    #[test]
+    fn test_lock_order_in_order() {
+        let outer = SpinLock::with_level(0, 1, "outer");
+        let inner = SpinLock::with_level(0, 2, "inner");
+        let _outer = outer.lock().unwrap();
+        let _inner = inner.lock().unwrap();
+    }
+
+    #[cfg(feature = "lock-order")]
+    #[test]
+    #[should_panic(expected = "acquiring `outer` (level 1) while holding `inner` (level 2)")]
+    fn test_lock_order_violation() {
+        let outer = SpinLock::with_level(0, 1, "outer");
+        let inner = SpinLock::with_level(0, 2, "inner");
+        let _inner = inner.lock().unwrap();
+        let _outer = outer.lock().unwrap();
+    }
+
+    #[cfg(feature = "lock-order")]
+    #[test]
+    fn test_lock_order_guard_dropped_on_other_thread() {
+        let outer = SpinLock::with_level(0, 1, "outer");
+        let inner = Arc::new(SpinLock::with_level(0, 2, "inner"));
+        let guard = inner.lock_arc().unwrap();
+        thread::spawn(move || drop(guard)).join().unwrap();
+        let _outer = outer.lock().unwrap();
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_lock_stats() {
+        let spin_lock = Arc::new(SpinLock::new(0));
+        let lock_clone = Arc::clone(&spin_lock);
+        stress(4, 100).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                let mut guard = lock_clone.lock().unwrap();
+                *guard += 1;
+                thread::yield_now();
+            }
+        });
+        let guard = spin_lock.lock().unwrap();
+        thread::sleep(Duration::from_millis(5));
+        drop(guard);
+        assert!(spin_lock.try_lock().is_ok());
+
+        let stats = spin_lock.stats();
+        assert_eq!(stats.acquisitions, 402);
+        assert!(stats.contended_acquisitions <= 400);
+        assert!(stats.spin_iterations >= stats.contended_acquisitions);
+        assert!(stats.max_hold_time >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_lock_both_opposite_orders() {
+        let a = Arc::new(SpinLock::new(0));
+        let b = Arc::new(SpinLock::new(0));
+        let (a_clone, b_clone) = (Arc::clone(&a), Arc::clone(&b));
+        stress(4, 500).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                let (mut first, mut second) = if ctx.thread % 2 == 0 {
+                    SpinLock::lock_both(&*a_clone, &*b_clone).unwrap()
+                } else {
+                    let (second, first) = SpinLock::lock_both(&*b_clone, &*a_clone).unwrap();
+                    (first, second)
+                };
+                *first += 1;
+                *second += 1;
+            }
+        });
+        assert_eq!((*a.lock().unwrap(), *b.lock().unwrap()), (2000, 2000));
+    }
+
+    #[test]
+    fn test_lock_all_preserves_order() {
+        let locks: Vec<SpinLock<usize>> = (0..4).map(SpinLock::new).collect();
+        let order = [&locks[2], &locks[0], &locks[3], &locks[1]];
+        let guards = SpinLock::lock_all(&order).unwrap();
+        let values: Vec<usize> = guards.iter().map(|guard| **guard).collect();
+        assert_eq!(values, vec![2, 0, 3, 1]);
+        assert!(locks.iter().all(|lock| lock.try_lock().is_err()));
+    }
+
+    #[test]
+    fn test_locks_in_statics() {
+        static SPIN_LOCK: SpinLock<usize> = SpinLock::new(0);
+        static MUTEX: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        static CONDVAR: SpinCondvar = SpinCondvar::new();
+        thread::scope(|s| {
+            for i in 0..4 {
+                s.spawn(move || {
+                    *SPIN_LOCK.lock().unwrap() += 1;
+                    MUTEX.lock().push(i);
+                    CONDVAR.notify_all();
+                });
+            }
+        });
+        assert_eq!(*SPIN_LOCK.lock().unwrap(), 4);
+        assert_eq!(MUTEX.lock().len(), 4);
+    }
+
+    #[test]
+    fn test_lock_arc() {
+        fn take(lock: &Arc<SpinLock<Vec<i32>>>) -> ArcLockGuard<Vec<i32>> {
+            lock.lock_arc().unwrap()
+        }
+
+        let spin_lock = Arc::new(SpinLock::new(vec![]));
+        let mut guard = take(&spin_lock);
+        guard.push(1);
+        thread::spawn(move || guard.push(2)).join().unwrap();
+        assert_eq!(*spin_lock.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_ticket_lock() {
+        let ticket_lock = Arc::new(TicketLock::new(0));
+        let lock_clone = Arc::clone(&ticket_lock);
+        stress(4, 1000).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                *lock_clone.lock() += 1;
+            }
+        });
+        assert_eq!(*ticket_lock.lock(), 4000);
+
+        let guard = ticket_lock.try_lock().unwrap();
+        assert!(ticket_lock.try_lock().is_none());
+        drop(guard);
+        assert!(ticket_lock.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_mcs_lock() {
+        let mcs_lock = Arc::new(McsLock::new(0));
+        let lock_clone = Arc::clone(&mcs_lock);
+        stress(4, 1000).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                *lock_clone.lock() += 1;
+            }
+        });
+        assert_eq!(*mcs_lock.lock(), 4000);
+    }
+
+    #[test]
+    fn test_blocking_mutex() {
+        let mutex = Arc::new(Mutex::new(0));
+        let mutex_clone = Arc::clone(&mutex);
+        stress(8, 50).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                let mut guard = mutex_clone.lock();
+                let value = *guard;
+                thread::yield_now();
+                *guard = value + 1;
+            }
+        });
+        assert_eq!(*mutex.lock(), 400);
+    }
+
+    #[test]
+    fn test_reentrant_lock() {
+        let lock = Arc::new(ReentrantLock::new(std::cell::Cell::new(0)));
+        let lock_clone = Arc::clone(&lock);
+        stress(4, 100).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                let outer = lock_clone.lock();
+                //  re-entering from the same thread must not deadlock
+                let inner = lock_clone.lock();
+                inner.set(inner.get() + 1);
+                drop(inner);
+                outer.set(outer.get() + 1);
+            }
+        });
+        assert_eq!(lock.lock().get(), 800);
+    }
+
+    #[test]
+    fn test_spin_condvar() {
+        let queue = SpinLock::new(VecDeque::new());
+        let condvar = SpinCondvar::new();
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let mut received = vec![];
+                    while received.len() < 50 {
+                        let mut guard = queue.lock().unwrap();
+                        while guard.is_empty() {
+                            guard = condvar.wait(guard).unwrap();
+                        }
+                        received.push(guard.pop_front().unwrap());
+                    }
+                    received
+                });
+            }
+            for i in 0..100 {
+                queue.lock().unwrap().push_back(i);
+                condvar.notify_one();
+            }
+        });
+        assert!(queue.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_adaptive_mutex() {
+        let mutex = Arc::new(AdaptiveMutex::new(0));
+        let mutex_clone = Arc::clone(&mutex);
+        stress(8, 50).run(move |ctx| {
+            for i in 0..ctx.iterations {
+                let mut guard = mutex_clone.lock();
+                let value = *guard;
+                //  mix short critical sections with ones long enough to force parking
+                if i % 10 == 0 {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                *guard = value + 1;
+            }
+        });
+        assert_eq!(*mutex.lock(), 400);
+
+        let guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_none());
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+    }
+
+    //  runs `lock` on a few threads while another keeps unparking them, so parked waiters see
+    //  wakeups that did not come from an unlock
+    fn spurious_unparks(lock: impl Fn() + Send + Sync + 'static) {
+        let lock = Arc::new(lock);
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let lockers: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let done_tx = done_tx.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        lock();
+                    }
+                    done_tx.send(()).unwrap();
+                })
+            })
+            .collect();
+        let finished = Arc::new(AtomicBool::new(false));
+        let spammer = {
+            let finished = Arc::clone(&finished);
+            let threads: Vec<_> = lockers.iter().map(|h| h.thread().clone()).collect();
+            thread::spawn(move || {
+                while !finished.load(std::sync::atomic::Ordering::Relaxed) {
+                    for thread in &threads {
+                        thread.unpark();
+                    }
+                    thread::yield_now();
+                }
+            })
+        };
+        //  a lost wakeup leaves a locker parked forever, so fail instead of hanging
+        for _ in 0..lockers.len() {
+            done_rx
+                .recv_timeout(Duration::from_secs(10))
+                .expect("a waiter was never woken");
+        }
+        finished.store(true, std::sync::atomic::Ordering::Relaxed);
+        spammer.join().unwrap();
+        for locker in lockers {
+            locker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mutex_spurious_unpark() {
+        let mutex = Arc::new(Mutex::new(0));
+        let mutex_clone = Arc::clone(&mutex);
+        spurious_unparks(move || {
+            let mut guard = mutex_clone.lock();
+            let value = *guard;
+            thread::sleep(Duration::from_micros(50));
+            *guard = value + 1;
+        });
+        assert_eq!(*mutex.lock(), 800);
+        assert!(mutex.parking.waiters.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_adaptive_mutex_spurious_unpark() {
+        let mutex = Arc::new(AdaptiveMutex::new(0));
+        let mutex_clone = Arc::clone(&mutex);
+        spurious_unparks(move || {
+            let mut guard = mutex_clone.lock();
+            let value = *guard;
+            thread::sleep(Duration::from_micros(50));
+            *guard = value + 1;
+        });
+        assert_eq!(*mutex.lock(), 800);
+        assert!(mutex.parking.waiters.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parking_mutex() {
+        let mutex = Arc::new(ParkingMutex::new(0));
+        let mutex_clone = Arc::clone(&mutex);
+        stress(8, 50).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                let mut guard = mutex_clone.lock();
+                let value = *guard;
+                thread::yield_now();
+                *guard = value + 1;
+            }
+        });
+        assert_eq!(*mutex.lock(), 400);
+        assert_eq!(
+            mutex.state.load(std::sync::atomic::Ordering::Relaxed),
+            UNLOCKED
+        );
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+    };
+
+    use super::*;
+
+    //  the value itself lives in a std UnsafeCell that loom cannot see, so a flag set for the
+    //  length of the critical section is what catches two holders overlapping
+    fn critical_section(inside: &AtomicBool) {
+        assert!(!inside.swap(true, std::sync::atomic::Ordering::Relaxed));
+        inside.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    #[test]
+    fn loom_spin_lock_excludes() {
+        loom::model(|| {
+            let lock = Arc::new(SpinLock::new(0));
+            let inside = Arc::new(AtomicBool::new(false));
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let (lock, inside) = (Arc::clone(&lock), Arc::clone(&inside));
+                    thread::spawn(move || {
+                        let mut guard = lock.lock().unwrap();
+                        critical_section(&inside);
+                        *guard += 1;
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(*lock.lock().unwrap(), 2);
+        });
+    }
+
+    #[test]
+    fn loom_spin_lock_try_lock() {
+        loom::model(|| {
+            let lock = Arc::new(SpinLock::new(()));
+            let inside = Arc::new(AtomicBool::new(false));
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let (lock, inside) = (Arc::clone(&lock), Arc::clone(&inside));
+                    thread::spawn(move || {
+                        if let Ok(_guard) = lock.try_lock() {
+                            critical_section(&inside);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert!(!lock.is_locked());
+        });
     }
 }
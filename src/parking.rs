@@ -0,0 +1,205 @@
+#![allow(dead_code)]
+
+//  A global table where threads park keyed by an address, so that a lock only needs to store a
+//  couple of state bits instead of its own waiter queue.
+
+use std::{
+    collections::VecDeque,
+    sync::{atomic::AtomicBool, Arc},
+    thread::{self, Thread},
+    time::Instant,
+};
+
+use crate::mutex::SpinLock;
+
+const BUCKETS: usize = 64;
+
+struct Waiter {
+    addr: usize,
+    thread: Thread,
+    unparked: AtomicBool,
+}
+
+struct Bucket {
+    queue: SpinLock<VecDeque<Arc<Waiter>>>,
+}
+
+impl Bucket {
+    const fn new() -> Self {
+        Self {
+            queue: SpinLock::new(VecDeque::new()),
+        }
+    }
+}
+
+static TABLE: [Bucket; BUCKETS] = [const { Bucket::new() }; BUCKETS];
+
+fn bucket(addr: usize) -> &'static Bucket {
+    //  Fibonacci hashing spreads neighbouring addresses over the buckets
+    let hash = addr.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+    &TABLE[hash >> (usize::BITS - BUCKETS.trailing_zeros())]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnparkResult {
+    pub unparked: bool,
+    pub have_more_waiters: bool,
+}
+
+/// Parks the current thread on `addr` until a matching `unpark_*` call. `validate` runs while the
+/// bucket is locked, so no unpark for `addr` can slip in between it and the thread being queued;
+/// if it returns false the thread does not park and `false` is returned.
+pub fn park(addr: usize, validate: impl FnOnce() -> bool) -> bool {
+    park_inner(addr, validate, None)
+}
+
+/// Like `park`, but gives up at `deadline`, in which case `false` is returned as well.
+pub fn park_until(addr: usize, validate: impl FnOnce() -> bool, deadline: Instant) -> bool {
+    park_inner(addr, validate, Some(deadline))
+}
+
+fn park_inner(addr: usize, validate: impl FnOnce() -> bool, deadline: Option<Instant>) -> bool {
+    let waiter = Arc::new(Waiter {
+        addr,
+        thread: thread::current(),
+        unparked: AtomicBool::new(false),
+    });
+    {
+        let mut queue = bucket(addr).queue.lock().unwrap();
+        if !validate() {
+            return false;
+        }
+        queue.push_back(Arc::clone(&waiter));
+    }
+    let mut deadline = deadline;
+    while !waiter.unparked.load(std::sync::atomic::Ordering::Acquire) {
+        let Some(until) = deadline else {
+            thread::park();
+            continue;
+        };
+        let now = Instant::now();
+        if now < until {
+            thread::park_timeout(until - now);
+            continue;
+        }
+        //  timed out: leave the queue, unless an unpark has already taken us off it, in which case
+        //  its wakeup is on the way and counts
+        let mut queue = bucket(addr).queue.lock().unwrap();
+        if let Some(pos) = queue.iter().position(|queued| Arc::ptr_eq(queued, &waiter)) {
+            queue.remove(pos);
+            return false;
+        }
+        deadline = None;
+    }
+    true
+}
+
+/// Wakes the longest-waiting thread parked on `addr`. `callback` runs while the bucket is still
+/// locked, which lets the caller update its state before any new thread can park.
+pub fn unpark_one(addr: usize, callback: impl FnOnce(UnparkResult)) -> UnparkResult {
+    let (waiter, result) = {
+        let mut queue = bucket(addr).queue.lock().unwrap();
+        let waiter = queue
+            .iter()
+            .position(|waiter| waiter.addr == addr)
+            .and_then(|pos| queue.remove(pos));
+        let result = UnparkResult {
+            unparked: waiter.is_some(),
+            have_more_waiters: queue.iter().any(|waiter| waiter.addr == addr),
+        };
+        callback(result);
+        (waiter, result)
+    };
+    if let Some(waiter) = waiter {
+        wake(&waiter);
+    }
+    result
+}
+
+/// Wakes every thread parked on `addr` and returns how many there were.
+pub fn unpark_all(addr: usize) -> usize {
+    let waiters: Vec<Arc<Waiter>> = {
+        let mut queue = bucket(addr).queue.lock().unwrap();
+        let mut matching = vec![];
+        queue.retain(|waiter| {
+            if waiter.addr == addr {
+                matching.push(Arc::clone(waiter));
+                false
+            } else {
+                true
+            }
+        });
+        matching
+    };
+    for waiter in &waiters {
+        wake(waiter);
+    }
+    waiters.len()
+}
+
+fn wake(waiter: &Waiter) {
+    waiter
+        .unparked
+        .store(true, std::sync::atomic::Ordering::Release);
+    waiter.thread.unpark();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_park_validate_fails() {
+        assert!(!park(1, || false));
+        assert_eq!(unpark_all(1), 0);
+    }
+
+    #[test]
+    fn test_park_until_times_out() {
+        let addr = 0x2000;
+        let start = Instant::now();
+        assert!(!park_until(
+            addr,
+            || true,
+            start + Duration::from_millis(10)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(unpark_all(addr), 0);
+    }
+
+    #[test]
+    fn test_unpark_one_and_all() {
+        let addr = 0x1000;
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..3)
+                .map(|_| s.spawn(move || park(addr, || true)))
+                .collect();
+            while bucket(addr)
+                .queue
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|waiter| waiter.addr == addr)
+                .count()
+                < 3
+            {
+                thread::sleep(Duration::from_millis(1));
+            }
+            let mut seen = None;
+            unpark_one(addr, |result| seen = Some(result));
+            assert_eq!(
+                seen,
+                Some(UnparkResult {
+                    unparked: true,
+                    have_more_waiters: true,
+                })
+            );
+            assert_eq!(unpark_all(addr), 2);
+            for handle in handles {
+                assert!(handle.join().unwrap());
+            }
+        });
+    }
+}
@@ -0,0 +1,269 @@
+#![allow(dead_code)]
+
+//  Stages of an ETL-style job, each on its own threads, connected by bounded `SharedQueue`s. The
+//  end of the input travels down the pipeline as a close: the source closes its queue when it runs
+//  out, and the last worker of each later stage to find its input closed and drained closes the
+//  next one. A stage that panics or finds its output closed closes its input as well, so the
+//  stages upstream stop instead of blocking on a queue nobody empties.
+
+use std::{
+    panic,
+    sync::{atomic::AtomicUsize, Arc},
+    thread::{self, JoinHandle},
+};
+
+use crate::bounded_queue::SharedQueue;
+
+type Queue<T> = SharedQueue<T, Box<[T]>>;
+
+//  lets a worker close the queues on either side without knowing their item types
+trait Edge: Send + Sync {
+    fn close(&self);
+}
+
+impl<T> Edge for Queue<T>
+where
+    T: Copy + Send,
+{
+    fn close(&self) {
+        SharedQueue::close(self);
+    }
+}
+
+type Worker = Box<dyn FnOnce() + Send>;
+
+//  held by every worker for as long as it runs, panics included
+struct WorkerExit {
+    remaining: Arc<AtomicUsize>,
+    input: Option<Arc<dyn Edge>>,
+    output: Option<Arc<dyn Edge>>,
+}
+
+impl Drop for WorkerExit {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Some(input) = &self.input {
+                input.close();
+            }
+        }
+        if self
+            .remaining
+            .fetch_sub(1, std::sync::atomic::Ordering::AcqRel)
+            == 1
+        {
+            if let Some(output) = &self.output {
+                output.close();
+            }
+        }
+    }
+}
+
+/// A pipeline still being put together, whose last stage produces `T`s. Nothing runs until
+/// `run` or `spawn` is called on the finished pipeline.
+pub struct Pipeline<T> {
+    workers: Vec<Worker>,
+    source: Arc<dyn Edge>,
+    output: Arc<Queue<T>>,
+}
+
+impl<T> Pipeline<T>
+where
+    T: Copy + Default + Send + 'static,
+{
+    /// Starts with a thread that puts every item of `items` into a queue of `depth` slots.
+    pub fn source<I>(depth: usize, items: I) -> Self
+    where
+        I: IntoIterator<Item = T> + Send + 'static,
+    {
+        let output = Arc::new(Queue::with_capacity(depth));
+        let exit = WorkerExit {
+            remaining: Arc::new(AtomicUsize::new(1)),
+            input: None,
+            output: Some(Arc::clone(&output) as Arc<dyn Edge>),
+        };
+        let queue = Arc::clone(&output);
+        let worker: Worker = Box::new(move || {
+            let _exit = exit;
+            for item in items {
+                if queue.put(item).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            workers: vec![worker],
+            source: Arc::clone(&output) as Arc<dyn Edge>,
+            output,
+        }
+    }
+
+    /// Adds a stage of `workers` threads applying `f` to every item, feeding a queue of `depth`
+    /// slots. With more than one worker the items can come out in a different order.
+    pub fn map<U, F>(self, depth: usize, workers: usize, f: F) -> Pipeline<U>
+    where
+        U: Copy + Default + Send + 'static,
+        F: Fn(T) -> U + Send + Sync + 'static,
+    {
+        let output = Arc::new(Queue::with_capacity(depth));
+        let next = Arc::clone(&output);
+        let pipeline = self.stage(
+            workers,
+            Some(Arc::clone(&output) as Arc<dyn Edge>),
+            move |input| {
+                while let Ok(item) = input.get() {
+                    if next.put(f(item)).is_err() {
+                        //  nothing downstream takes items any more, so stop the stages upstream
+                        input.close();
+                        break;
+                    }
+                }
+            },
+        );
+        Pipeline {
+            workers: pipeline.workers,
+            source: pipeline.source,
+            output,
+        }
+    }
+
+    /// Ends the pipeline with `workers` threads handing every item to `f`.
+    pub fn sink<F>(self, workers: usize, f: F) -> CompletePipeline
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let pipeline = self.stage(workers, None, move |input| {
+            while let Ok(item) = input.get() {
+                f(item);
+            }
+        });
+        CompletePipeline {
+            workers: pipeline.workers,
+            source: pipeline.source,
+        }
+    }
+
+    fn stage(
+        mut self,
+        workers: usize,
+        output: Option<Arc<dyn Edge>>,
+        work: impl Fn(&Queue<T>) + Send + Sync + 'static,
+    ) -> Self {
+        assert!(workers > 0, "a stage needs at least one worker");
+        let remaining = Arc::new(AtomicUsize::new(workers));
+        let work = Arc::new(work);
+        for _ in 0..workers {
+            let exit = WorkerExit {
+                remaining: Arc::clone(&remaining),
+                input: Some(Arc::clone(&self.output) as Arc<dyn Edge>),
+                output: output.clone(),
+            };
+            let input = Arc::clone(&self.output);
+            let work = Arc::clone(&work);
+            self.workers.push(Box::new(move || {
+                let _exit = exit;
+                work(&input);
+            }));
+        }
+        self
+    }
+}
+
+/// A pipeline from source to sink, ready to run.
+pub struct CompletePipeline {
+    workers: Vec<Worker>,
+    source: Arc<dyn Edge>,
+}
+
+impl CompletePipeline {
+    /// Runs every stage until the source runs out and everything it produced has reached the
+    /// sink. A panic in any stage stops the pipeline and is resumed here.
+    pub fn run(self) {
+        self.spawn().join();
+    }
+
+    /// Starts every stage and returns without waiting for them.
+    pub fn spawn(self) -> RunningPipeline {
+        let threads = self.workers.into_iter().map(thread::spawn).collect();
+        RunningPipeline {
+            threads,
+            source: self.source,
+        }
+    }
+}
+
+pub struct RunningPipeline {
+    threads: Vec<JoinHandle<()>>,
+    source: Arc<dyn Edge>,
+}
+
+impl RunningPipeline {
+    /// Stops the source early. Whatever it already produced still goes through the rest of the
+    /// pipeline.
+    pub fn close(&self) {
+        self.source.close();
+    }
+
+    /// Waits for every stage to drain and finish, resuming the first panic if a stage panicked.
+    pub fn join(self) {
+        let mut panicked = None;
+        for thread in self.threads {
+            if let Err(payload) = thread.join() {
+                panicked.get_or_insert(payload);
+            }
+        }
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&received);
+        Pipeline::source(4, 0..1000u64)
+            .map(2, 3, |x| x * 2)
+            .map(1, 1, |x| x + 1)
+            .sink(2, move |x| sink.lock().unwrap().push(x))
+            .run();
+        let mut received = received.lock().unwrap().clone();
+        received.sort_unstable();
+        assert_eq!(received, (0..1000).map(|x| x * 2 + 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_pipeline_close_drains() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&received);
+        let running = Pipeline::source(4, 0u64..)
+            .map(4, 1, |x| x + 1)
+            .sink(1, move |x| sink.lock().unwrap().push(x))
+            .spawn();
+        while received.lock().unwrap().len() < 100 {
+            thread::yield_now();
+        }
+        running.close();
+        running.join();
+        //  single workers keep the order, and nothing the source produced was lost
+        let received = received.lock().unwrap();
+        assert_eq!(*received, (1..=received.len() as u64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "bad item")]
+    fn test_pipeline_stage_panics() {
+        Pipeline::source(2, 0u64..)
+            .map(2, 2, |x| {
+                assert!(x != 10, "bad item");
+                x
+            })
+            .sink(1, |_| {})
+            .run();
+    }
+}
@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+
+//  The channel API over a byte stream, so the two ends can live in different processes. Every
+//  message is serialized to JSON and sent as one frame: a big-endian u32 length, then the bytes.
+
+use std::{
+    error, fmt,
+    io::{self, BufReader, Read, Write},
+    marker::PhantomData,
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+//  a corrupt length prefix should fail the receive, not allocate gigabytes
+const MAX_FRAME: usize = 16 << 20;
+
+#[derive(Debug)]
+pub enum RecvError {
+    /// The sending side closed the stream between two messages.
+    Disconnected,
+    Io(io::Error),
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => write!(f, "sender closed the stream"),
+            RecvError::Io(err) => write!(f, "reading a frame failed: {}", err),
+            RecvError::Decode(err) => write!(f, "decoding a frame failed: {}", err),
+        }
+    }
+}
+
+impl error::Error for RecvError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RecvError::Disconnected => None,
+            RecvError::Io(err) => Some(err),
+            RecvError::Decode(err) => Some(err),
+        }
+    }
+}
+
+/// A connected pair within this process, mostly useful for tests and for handing one end to a
+/// child process.
+pub fn pair<T>() -> io::Result<(Sender<T>, Receiver<T>)> {
+    let (sending, receiving) = UnixStream::pair()?;
+    Ok((Sender::new(sending), Receiver::new(receiving)))
+}
+
+pub fn connect_unix<T>(path: impl AsRef<Path>) -> io::Result<Sender<T>> {
+    Ok(Sender::new(UnixStream::connect(path)?))
+}
+
+pub fn connect_tcp<T>(addr: &str) -> io::Result<Sender<T, TcpStream>> {
+    Ok(Sender::new(TcpStream::connect(addr)?))
+}
+
+pub struct Sender<T, S = UnixStream> {
+    stream: S,
+    _message: PhantomData<fn(T)>,
+}
+
+impl<T, S> Sender<T, S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T, S> Sender<T, S>
+where
+    T: Serialize,
+    S: Write,
+{
+    /// Fails once the receiving end has closed the stream, typically with `BrokenPipe`.
+    pub fn send(&mut self, message: &T) -> io::Result<()> {
+        //  header and payload go out in a single write, so a failure never leaves half a header
+        let mut frame = vec![0; 4];
+        serde_json::to_writer(&mut frame, message)?;
+        let len = frame.len() - 4;
+        if len > MAX_FRAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message exceeds the maximum frame size",
+            ));
+        }
+        frame[..4].copy_from_slice(&(len as u32).to_be_bytes());
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+pub struct Receiver<T, S = UnixStream> {
+    stream: BufReader<S>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T, S> Receiver<T, S>
+where
+    S: Read,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T, S> Receiver<T, S>
+where
+    T: DeserializeOwned,
+    S: Read,
+{
+    /// Blocks until a whole frame has arrived. A stream that ends in the middle of a frame is an
+    /// `Io` error, not a disconnect.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        let mut header = [0; 4];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.stream.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Err(RecvError::Disconnected),
+                Ok(0) => return Err(RecvError::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(RecvError::Io(err)),
+            }
+        }
+        let len = u32::from_be_bytes(header) as usize;
+        if len > MAX_FRAME {
+            return Err(RecvError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame exceeds the maximum frame size",
+            )));
+        }
+        let mut payload = vec![0; len];
+        self.stream
+            .read_exact(&mut payload)
+            .map_err(RecvError::Io)?;
+        serde_json::from_slice(&payload).map_err(RecvError::Decode)
+    }
+}
+
+impl<T, S> Iterator for Receiver<T, S>
+where
+    T: DeserializeOwned,
+    S: Read,
+{
+    type Item = Result<T, RecvError>;
+
+    /// Ends when the sender disconnects.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.recv() {
+            Err(RecvError::Disconnected) => None,
+            result => Some(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, os::unix::net::UnixListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_pair() {
+        let (mut sender, receiver) = pair::<(u32, String)>().unwrap();
+        let receiving = thread::spawn(move || receiver.collect::<Result<Vec<_>, _>>());
+        for i in 0..100 {
+            sender.send(&(i, "x".repeat(i as usize))).unwrap();
+        }
+        drop(sender);
+        let received = receiving.join().unwrap().unwrap();
+        assert_eq!(received.len(), 100);
+        assert_eq!(received[42], (42, "x".repeat(42)));
+    }
+
+    #[test]
+    fn test_unix_and_tcp() {
+        let path = std::env::temp_dir().join(format!("atomics-remote-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut sender = connect_unix::<Vec<u8>>(&path).unwrap();
+        let mut receiver = Receiver::<Vec<u8>>::new(listener.accept().unwrap().0);
+        sender.send(&vec![1, 2, 3]).unwrap();
+        assert_eq!(receiver.recv().unwrap(), vec![1, 2, 3]);
+        drop(receiver);
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut sender = connect_tcp::<String>(&addr).unwrap();
+        let mut receiver = Receiver::<String, _>::new(listener.accept().unwrap().0);
+        sender.send(&"hello".to_string()).unwrap();
+        drop(sender);
+        assert_eq!(receiver.recv().unwrap(), "hello");
+        assert!(matches!(receiver.recv(), Err(RecvError::Disconnected)));
+    }
+
+    #[test]
+    fn test_bad_frames() {
+        let (mut sending, receiving) = UnixStream::pair().unwrap();
+        let mut receiver = Receiver::<u32>::new(receiving);
+        sending.write_all(&3u32.to_be_bytes()).unwrap();
+        sending.write_all(b"\"x\"").unwrap();
+        assert!(matches!(receiver.recv(), Err(RecvError::Decode(_))));
+        sending.write_all(&[0, 0]).unwrap();
+        drop(sending);
+        assert!(matches!(receiver.recv(), Err(RecvError::Io(_))));
+    }
+}
@@ -0,0 +1,155 @@
+#![allow(dead_code)]
+
+//  Request-response on top of the other channels: requests travel through a bounded mpmc channel,
+//  each one carrying the sending half of a fresh one-shot channel for its reply.
+
+use std::{
+    error::Error,
+    fmt,
+    sync::mpsc::{RecvError, SendError},
+};
+
+use crate::{
+    channel_split::{Channel, OwnedSender},
+    mpmc,
+};
+
+/// Why a call did not get a response.
+#[derive(PartialEq, Eq)]
+pub enum CallError<Req> {
+    /// Every handler is gone, so the request was never delivered.
+    Disconnected(Req),
+    /// The request was taken, but its responder was dropped without a reply, or the request was
+    /// still queued when the last handler went away.
+    NoReply,
+}
+
+impl<Req> fmt::Debug for CallError<Req> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Disconnected(_) => write!(f, "Disconnected(..)"),
+            CallError::NoReply => write!(f, "NoReply"),
+        }
+    }
+}
+
+impl<Req> fmt::Display for CallError<Req> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Disconnected(_) => write!(f, "no handler left to take the request"),
+            CallError::NoReply => write!(f, "request dropped without a reply"),
+        }
+    }
+}
+
+impl<Req> Error for CallError<Req> {}
+
+/// Creates a call channel that queues up to `capacity` requests for the handlers.
+pub fn channel<Req, Resp>(capacity: usize) -> (Caller<Req, Resp>, Handler<Req, Resp>) {
+    let (requests, incoming) = mpmc::bounded(capacity);
+    (Caller { requests }, Handler { incoming })
+}
+
+pub struct Caller<Req, Resp> {
+    requests: mpmc::Sender<(Req, Responder<Resp>)>,
+}
+
+impl<Req, Resp> Caller<Req, Resp> {
+    /// Sends `request` to one of the handlers and blocks until it responds.
+    pub fn call(&self, request: Req) -> Result<Resp, CallError<Req>> {
+        let (reply, response) = Channel::new_pair();
+        if let Err(err) = self.requests.send((request, Responder { reply })) {
+            return Err(CallError::Disconnected(err.into_inner().0));
+        }
+        response.receive().map_err(|RecvError| CallError::NoReply)
+    }
+}
+
+impl<Req, Resp> Clone for Caller<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+pub struct Handler<Req, Resp> {
+    incoming: mpmc::Receiver<(Req, Responder<Resp>)>,
+}
+
+impl<Req, Resp> Handler<Req, Resp> {
+    /// Blocks until a request arrives. Returns `Err` once every caller is gone.
+    pub fn recv(&self) -> Result<(Req, Responder<Resp>), mpmc::RecvError> {
+        self.incoming.recv()
+    }
+}
+
+impl<Req, Resp> Clone for Handler<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+/// Answers one request. Dropping it without responding fails the call with `NoReply`.
+pub struct Responder<Resp> {
+    reply: OwnedSender<Resp>,
+}
+
+impl<Resp> Responder<Resp> {
+    /// Hands the response back if the caller is no longer waiting for it.
+    pub fn respond(self, response: Resp) -> Result<(), SendError<Resp>> {
+        self.reply.send(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_call() {
+        let (caller, handler) = channel::<u64, u64>(4);
+        for _ in 0..2 {
+            let handler = handler.clone();
+            thread::spawn(move || {
+                while let Ok((request, responder)) = handler.recv() {
+                    responder.respond(request * request).unwrap();
+                }
+            });
+        }
+        drop(handler);
+        stress(4, 100).run(move |ctx| {
+            for i in 0..ctx.iterations as u64 {
+                assert_eq!(caller.call(i), Ok(i * i));
+            }
+        });
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let (caller, handler) = channel::<i32, i32>(1);
+        let handling = thread::spawn(move || {
+            let (request, responder) = handler.recv().unwrap();
+            drop(responder);
+            request
+        });
+        assert_eq!(caller.call(1), Err(CallError::NoReply));
+        assert_eq!(handling.join().unwrap(), 1);
+        assert_eq!(caller.call(2), Err(CallError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_call_queued_when_handlers_drop() {
+        let (caller, handler) = channel::<i32, i32>(1);
+        let calling = thread::spawn(move || caller.call(1));
+        //  let the request reach the queue, then drop the only handler without reading it
+        thread::sleep(Duration::from_millis(50));
+        drop(handler);
+        assert_eq!(calling.join().unwrap(), Err(CallError::NoReply));
+    }
+}
@@ -0,0 +1,346 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::UnsafeCell,
+    mem,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize},
+};
+
+//  the lowest bit marks a writer, the remaining bits count readers
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+pub struct RwSpinLock<T> {
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for RwSpinLock<T> where T: Send {}
+unsafe impl<T> Sync for RwSpinLock<T> where T: Send + Sync {}
+
+impl<T> RwSpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> RwReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwReadGuard<'_, T>> {
+        let state = self.state.load(std::sync::atomic::Ordering::Relaxed);
+        if state & WRITER != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange_weak(
+                state,
+                state + READER,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| RwReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwWriteGuard<'_, T> {
+        while self
+            .state
+            .compare_exchange_weak(
+                0,
+                WRITER,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+        RwWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(
+                0,
+                WRITER,
+                std::sync::atomic::Ordering::Acquire,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| RwWriteGuard { lock: self })
+    }
+}
+
+pub struct RwReadGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T> Deref for RwReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for RwReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock
+            .state
+            .fetch_sub(READER, std::sync::atomic::Ordering::Release);
+    }
+}
+
+pub struct RwWriteGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T> Deref for RwWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> RwWriteGuard<'a, T> {
+    /// Turns the write lock into a read lock without releasing it in between, so no other writer
+    /// can change the data before this thread reads it.
+    pub fn downgrade(self) -> RwReadGuard<'a, T> {
+        let lock = self.lock;
+        mem::forget(self);
+        //  the writer bit is only ever set with no readers, so this swaps it for one reader
+        lock.state
+            .fetch_add(READER - WRITER, std::sync::atomic::Ordering::Release);
+        RwReadGuard { lock }
+    }
+}
+
+impl<T> Drop for RwWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock
+            .state
+            .fetch_and(!WRITER, std::sync::atomic::Ordering::Release);
+    }
+}
+
+//  one reader count per shard, each on its own cache line so readers on different shards never
+//  touch the same line
+#[repr(align(128))]
+struct Shard {
+    readers: AtomicUsize,
+}
+
+const SHARDS: usize = 16;
+
+//  threads are spread round-robin over the shards in the order they first take a read lock
+fn current_shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SHARD: usize = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed) % SHARDS;
+    }
+    SHARD.with(|shard| *shard)
+}
+
+/// A reader-writer lock for read-mostly data. A read only touches the reader count of the calling
+/// thread's shard, so readers on different threads don't contend; a write has to wait for every
+/// shard to drain, which makes writes much more expensive than on `RwSpinLock`.
+pub struct ShardedRwLock<T> {
+    writer: AtomicBool,
+    shards: [Shard; SHARDS],
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for ShardedRwLock<T> where T: Send {}
+unsafe impl<T> Sync for ShardedRwLock<T> where T: Send + Sync {}
+
+impl<T> ShardedRwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            writer: AtomicBool::new(false),
+            shards: [const {
+                Shard {
+                    readers: AtomicUsize::new(0),
+                }
+            }; SHARDS],
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ShardedReadGuard<'_, T> {
+        let shard = &self.shards[current_shard()];
+        loop {
+            //  announce the read first and then look for a writer; the writer does the opposite, so
+            //  with SeqCst on both sides at least one of them sees the other
+            shard
+                .readers
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if !self.writer.load(std::sync::atomic::Ordering::SeqCst) {
+                return ShardedReadGuard { lock: self, shard };
+            }
+            shard
+                .readers
+                .fetch_sub(1, std::sync::atomic::Ordering::Release);
+            while self.writer.load(std::sync::atomic::Ordering::Relaxed) {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn write(&self) -> ShardedWriteGuard<'_, T> {
+        while self
+            .writer
+            .compare_exchange_weak(
+                false,
+                true,
+                std::sync::atomic::Ordering::SeqCst,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+        for shard in &self.shards {
+            while shard.readers.load(std::sync::atomic::Ordering::SeqCst) != 0 {
+                std::hint::spin_loop();
+            }
+        }
+        ShardedWriteGuard { lock: self }
+    }
+}
+
+pub struct ShardedReadGuard<'a, T> {
+    lock: &'a ShardedRwLock<T>,
+    shard: &'a Shard,
+}
+
+impl<T> Deref for ShardedReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for ShardedReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.shard
+            .readers
+            .fetch_sub(1, std::sync::atomic::Ordering::Release);
+    }
+}
+
+pub struct ShardedWriteGuard<'a, T> {
+    lock: &'a ShardedRwLock<T>,
+}
+
+impl<T> Deref for ShardedWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for ShardedWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for ShardedWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock
+            .writer
+            .store(false, std::sync::atomic::Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_readers_share_writers_exclude() {
+        let lock = RwSpinLock::new(1);
+        let first = lock.read();
+        let second = lock.read();
+        assert_eq!(*first + *second, 2);
+        assert!(lock.try_write().is_none());
+        drop((first, second));
+
+        let mut writer = lock.write();
+        *writer = 5;
+        assert!(lock.try_read().is_none());
+        drop(writer);
+        assert_eq!(*lock.read(), 5);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let lock = RwSpinLock::new(0);
+        let mut writer = lock.write();
+        *writer = 5;
+        let reader = writer.downgrade();
+        assert!(lock.try_write().is_none());
+        let other = lock.read();
+        assert_eq!((*reader, *other), (5, 5));
+        drop((reader, other));
+        assert!(lock.try_write().is_some());
+    }
+
+    #[test]
+    fn test_rw_spin_lock_threads() {
+        let lock = Arc::new(RwSpinLock::new((0, 0)));
+        let lock_clone = Arc::clone(&lock);
+        stress(4, 1000).run(move |ctx| {
+            for i in 0..ctx.iterations {
+                if ctx.thread == 0 || i % 10 == 0 {
+                    let mut guard = lock_clone.write();
+                    guard.0 += 1;
+                    guard.1 += 1;
+                } else {
+                    let guard = lock_clone.read();
+                    assert_eq!(guard.0, guard.1);
+                }
+            }
+        });
+        assert_eq!(*lock.read(), (1300, 1300));
+    }
+
+    #[test]
+    fn test_sharded_rw_lock() {
+        let lock = Arc::new(ShardedRwLock::new((0, 0)));
+        let lock_clone = Arc::clone(&lock);
+        stress(4, 1000).run(move |ctx| {
+            for i in 0..ctx.iterations {
+                if ctx.thread == 0 || i % 10 == 0 {
+                    let mut guard = lock_clone.write();
+                    guard.0 += 1;
+                    guard.1 += 1;
+                } else {
+                    let guard = lock_clone.read();
+                    assert_eq!(guard.0, guard.1);
+                }
+            }
+        });
+        let reader = lock.read();
+        assert_eq!(*reader, (1300, 1300));
+        assert_eq!(*lock.read(), (1300, 1300));
+    }
+}
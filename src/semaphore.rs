@@ -1,58 +1,240 @@
 #![allow(dead_code)]
 
-use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-struct Semaphore {
+use crate::sync::{atomic::AtomicBool, const_fn, Condvar, Mutex};
+
+pub(crate) struct Semaphore {
     value: Mutex<usize>,
     cond_var: Condvar,
+    //  only written with `value` locked, so a waiter cannot miss the wakeup from `close`
+    closed: AtomicBool,
 }
 
 impl Semaphore {
-    fn new(value: usize) -> Self {
-        Self {
-            value: Mutex::new(value),
-            cond_var: Condvar::new(),
+    const_fn! {
+        pub(crate) fn new(value: usize) -> Self {
+            Self {
+                value: Mutex::new(value),
+                cond_var: Condvar::new(),
+                closed: AtomicBool::new(false),
+            }
         }
     }
 
-    fn acquire(&self) {
+    /// Returns false without a permit if the semaphore is closed while none is available.
+    pub(crate) fn acquire(&self) -> bool {
         let mut guard = self.value.lock().unwrap();
-        while *guard <= 0 {
+        while *guard == 0 {
+            if self.is_closed() {
+                return false;
+            }
             guard = self.cond_var.wait(guard).unwrap();
         }
         *guard -= 1;
+        true
     }
 
-    fn release(&self) {
-        *self.value.lock().unwrap() += 1;
+    /// Blocks until there is at least one permit, then takes as many as are available up to
+    /// `max`. Returns 0 only once the semaphore is closed and empty.
+    pub(crate) fn acquire_up_to(&self, max: usize) -> usize {
+        let mut guard = self.value.lock().unwrap();
+        while *guard == 0 {
+            if self.is_closed() {
+                return 0;
+            }
+            guard = self.cond_var.wait(guard).unwrap();
+        }
+        let taken = (*guard).min(max);
+        *guard -= taken;
+        taken
+    }
+
+    /// Takes a permit only if one is available right away.
+    pub(crate) fn try_acquire(&self) -> bool {
+        let mut guard = self.value.lock().unwrap();
+        if *guard == 0 {
+            return false;
+        }
+        *guard -= 1;
+        true
+    }
+
+    /// Like `acquire`, but also gives up and returns false if no permit turns up within `timeout`.
+    pub(crate) fn acquire_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.value.lock().unwrap();
+        while *guard == 0 {
+            let now = Instant::now();
+            if now >= deadline || self.is_closed() {
+                return false;
+            }
+            guard = self.cond_var.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+        *guard -= 1;
+        true
+    }
+
+    pub(crate) fn release(&self) {
+        self.release_n(1);
+    }
+
+    pub(crate) fn release_n(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        *self.value.lock().unwrap() += n;
         self.cond_var.notify_all();
     }
+
+    /// Wakes every waiter. Permits that are left can still be taken, but once they run out
+    /// `acquire` stops waiting for more.
+    pub(crate) fn close(&self) {
+        let _guard = self.value.lock().unwrap();
+        self.closed
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        self.cond_var.notify_all();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(std::sync::atomic::Ordering::Relaxed)
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
-    use std::{sync::Arc, thread, time::Duration};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
 
     use super::*;
+    use crate::stress::stress;
 
     #[test]
     fn test_basic_sempahore() {
         let capacity = 5;
         let sem = Arc::new(Semaphore::new(capacity));
-        let mut handles = vec![];
-
-        for i in 0..capacity {
-            let sem_clone = Arc::clone(&sem);
-            handles.push(thread::spawn(move || {
-                sem_clone.acquire();
-                println!("Thread {} acquired", i);
-                thread::sleep(Duration::from_millis(100));
-                println!("Thread {} released", i);
-            }));
-        }
+        let holders = Arc::new(AtomicUsize::new(0));
+        let max_holders = Arc::new(AtomicUsize::new(0));
+        let (holders_clone, max_holders_clone) = (Arc::clone(&holders), Arc::clone(&max_holders));
 
-        for handle in handles {
-            handle.join().unwrap();
-        }
+        stress(capacity * 2, 10).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                sem.acquire();
+                let current = holders_clone.fetch_add(1, Ordering::SeqCst) + 1;
+                max_holders_clone.fetch_max(current, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(1));
+                holders_clone.fetch_sub(1, Ordering::SeqCst);
+                sem.release();
+            }
+        });
+
+        assert_eq!(holders.load(Ordering::SeqCst), 0);
+        assert!(max_holders.load(Ordering::SeqCst) <= capacity);
+    }
+
+    #[test]
+    fn test_try_acquire() {
+        let sem = Semaphore::new(1);
+        assert!(sem.try_acquire());
+        assert!(!sem.try_acquire());
+        sem.release();
+        assert!(sem.try_acquire());
+    }
+
+    #[test]
+    fn test_acquire_timeout() {
+        let sem = Arc::new(Semaphore::new(0));
+        assert!(!sem.acquire_timeout(Duration::from_millis(10)));
+        let releasing = {
+            let sem = Arc::clone(&sem);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                sem.release();
+            })
+        };
+        assert!(sem.acquire_timeout(Duration::from_secs(10)));
+        releasing.join().unwrap();
+    }
+
+    #[test]
+    fn test_acquire_up_to() {
+        let sem = Arc::new(Semaphore::new(3));
+        assert_eq!(sem.acquire_up_to(2), 2);
+        assert_eq!(sem.acquire_up_to(2), 1);
+        let releasing = {
+            let sem = Arc::clone(&sem);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                sem.release_n(5);
+            })
+        };
+        assert_eq!(sem.acquire_up_to(10), 5);
+        releasing.join().unwrap();
+        sem.close();
+        assert_eq!(sem.acquire_up_to(10), 0);
+    }
+
+    #[test]
+    fn test_close() {
+        let sem = Arc::new(Semaphore::new(1));
+        let waiting = {
+            let sem = Arc::clone(&sem);
+            thread::spawn(move || {
+                sem.acquire();
+                sem.acquire()
+            })
+        };
+        thread::sleep(Duration::from_millis(10));
+        sem.close();
+        assert!(!waiting.join().unwrap());
+        sem.release();
+        assert!(sem.acquire());
+        assert!(!sem.acquire_timeout(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_static_semaphore() {
+        static SEMAPHORE: Semaphore = Semaphore::new(1);
+        SEMAPHORE.acquire();
+        thread::spawn(|| SEMAPHORE.release()).join().unwrap();
+        SEMAPHORE.acquire();
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{
+        sync::{atomic::AtomicUsize, Arc},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn loom_semaphore_bounds_holders() {
+        loom::model(|| {
+            let sem = Arc::new(Semaphore::new(1));
+            let holders = Arc::new(AtomicUsize::new(0));
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let (sem, holders) = (Arc::clone(&sem), Arc::clone(&holders));
+                    thread::spawn(move || {
+                        sem.acquire();
+                        assert_eq!(holders.fetch_add(1, std::sync::atomic::Ordering::SeqCst), 0);
+                        holders.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                        sem.release();
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
     }
 }
@@ -0,0 +1,104 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{fence, AtomicUsize},
+};
+
+/// Readers never block writers: they copy the value out and retry if a write overlapped the copy.
+/// An odd sequence number means a write is in progress.
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for SeqLock<T> where T: Copy + Send {}
+unsafe impl<T> Sync for SeqLock<T> where T: Copy + Send {}
+
+impl<T> SeqLock<T>
+where
+    T: Copy,
+{
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        loop {
+            let before = self.seq.load(std::sync::atomic::Ordering::Acquire);
+            if before & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            //  the copy may race with a writer, in which case it is thrown away below
+            let value = unsafe { ptr::read_volatile(self.value.get()) };
+            fence(std::sync::atomic::Ordering::Acquire);
+            let after = self.seq.load(std::sync::atomic::Ordering::Relaxed);
+            if before == after {
+                return value;
+            }
+        }
+    }
+
+    pub fn write(&self, value: T) {
+        //  writers exclude each other by moving the sequence from even to odd
+        let mut seq = self.seq.load(std::sync::atomic::Ordering::Relaxed);
+        loop {
+            if seq & 1 == 0 {
+                match self.seq.compare_exchange_weak(
+                    seq,
+                    seq.wrapping_add(1),
+                    std::sync::atomic::Ordering::Acquire,
+                    std::sync::atomic::Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => seq = current,
+                }
+            } else {
+                std::hint::spin_loop();
+                seq = self.seq.load(std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+        fence(std::sync::atomic::Ordering::Release);
+        unsafe { ptr::write_volatile(self.value.get(), value) };
+        self.seq
+            .store(seq.wrapping_add(2), std::sync::atomic::Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::stress::stress;
+
+    #[test]
+    fn test_seqlock() {
+        let lock = SeqLock::new(1);
+        assert_eq!(lock.read(), 1);
+        lock.write(2);
+        assert_eq!(lock.read(), 2);
+    }
+
+    #[test]
+    fn test_seqlock_readers_never_see_torn_writes() {
+        let lock = Arc::new(SeqLock::new((0u64, 0u64)));
+        let lock_clone = Arc::clone(&lock);
+        stress(4, 10_000).run(move |ctx| {
+            for i in 0..ctx.iterations as u64 {
+                if ctx.thread == 0 {
+                    lock_clone.write((i, i));
+                } else {
+                    let (a, b) = lock_clone.read();
+                    assert_eq!(a, b);
+                }
+            }
+        });
+        assert_eq!(lock.read(), (9_999, 9_999));
+    }
+}
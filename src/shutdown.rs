@@ -0,0 +1,191 @@
+#![allow(dead_code)]
+
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        let (cancelled, cond_var) = &*self.inner;
+        *cancelled.lock().unwrap() = true;
+        cond_var.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+
+    pub fn wait(&self) {
+        let (cancelled, cond_var) = &*self.inner;
+        let mut guard = cancelled.lock().unwrap();
+        while !*guard {
+            guard = cond_var.wait(guard).unwrap();
+        }
+    }
+
+    /// Returns whether the token was cancelled before the timeout elapsed.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (cancelled, cond_var) = &*self.inner;
+        let guard = cancelled.lock().unwrap();
+        let (guard, _) = cond_var
+            .wait_timeout_while(guard, timeout, |cancelled| !*cancelled)
+            .unwrap();
+        *guard
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct WaitGroup {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl WaitGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a unit of in-flight work that lasts until the returned guard is dropped.
+    pub fn add(&self) -> WorkGuard {
+        *self.inner.0.lock().unwrap() += 1;
+        WorkGuard {
+            wait_group: self.clone(),
+        }
+    }
+
+    pub fn wait(&self) {
+        let (count, cond_var) = &*self.inner;
+        let mut guard = count.lock().unwrap();
+        while *guard > 0 {
+            guard = cond_var.wait(guard).unwrap();
+        }
+    }
+}
+
+pub struct WorkGuard {
+    wait_group: WaitGroup,
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        let (count, cond_var) = &*self.wait_group.inner;
+        let mut guard = count.lock().unwrap();
+        *guard -= 1;
+        if *guard == 0 {
+            cond_var.notify_all();
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    wait_group: WaitGroup,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a coordinator that is cancelled by the first SIGINT or SIGTERM. A second signal
+    /// exits the process immediately without waiting for in-flight work.
+    pub fn install() -> io::Result<Self> {
+        let shutdown = Self::new();
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let token = shutdown.token();
+        thread::Builder::new()
+            .name("shutdown-signals".to_string())
+            .spawn(move || {
+                let mut signals = signals.forever();
+                if signals.next().is_some() {
+                    token.cancel();
+                }
+                if let Some(signal) = signals.next() {
+                    std::process::exit(128 + signal);
+                }
+            })?;
+        Ok(shutdown)
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn track(&self) -> WorkGuard {
+        self.wait_group.add()
+    }
+
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Blocks until every guard handed out by `track` has been dropped.
+    pub fn drain(&self) {
+        self.wait_group.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token_wakes_waiters() {
+        let token = CancellationToken::new();
+        assert!(!token.wait_timeout(Duration::from_millis(10)));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| token.wait());
+            }
+            token.cancel();
+        });
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_drain_waits_for_in_flight_work() {
+        let shutdown = Shutdown::new();
+        let start = Instant::now();
+        let token = shutdown.token();
+        let guard = shutdown.track();
+        let handle = thread::spawn(move || {
+            let _guard = guard;
+            token.wait();
+            thread::sleep(Duration::from_millis(50));
+        });
+        shutdown.trigger();
+        shutdown.drain();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_signal_cancels_token() {
+        let shutdown = Shutdown::install().unwrap();
+        signal_hook::low_level::raise(SIGTERM).unwrap();
+        assert!(shutdown.token().wait_timeout(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Per-thread view handed to the closure passed to [`Stress::run`].
+pub struct Context {
+    pub thread: usize,
+    pub threads: usize,
+    pub iterations: usize,
+}
+
+pub struct Stress {
+    threads: usize,
+    iterations: usize,
+    timeout: Duration,
+}
+
+pub fn stress(threads: usize, iterations: usize) -> Stress {
+    Stress {
+        threads,
+        iterations,
+        timeout: DEFAULT_TIMEOUT,
+    }
+}
+
+impl Stress {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs `f` once on each of `threads` threads, all released at the same time, and returns
+    /// the per-thread results ordered by thread index.
+    ///
+    /// Panics if any thread panicked (reporting every panic message) or if the threads have not
+    /// all finished within the timeout. Threads still running after a timeout are leaked.
+    pub fn run<F, R>(self, f: F) -> Vec<R>
+    where
+        F: Fn(Context) -> R + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        let f = Arc::new(f);
+        let barrier = Arc::new(Barrier::new(self.threads));
+        let (tx, rx) = mpsc::channel();
+        for i in 0..self.threads {
+            let f = Arc::clone(&f);
+            let barrier = Arc::clone(&barrier);
+            let tx = tx.clone();
+            let ctx = Context {
+                thread: i,
+                threads: self.threads,
+                iterations: self.iterations,
+            };
+            thread::Builder::new()
+                .name(format!("stress-{}", i))
+                .spawn(move || {
+                    barrier.wait();
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| f(ctx)));
+                    let _ = tx.send((i, result));
+                })
+                .unwrap();
+        }
+        drop(tx);
+
+        let deadline = Instant::now() + self.timeout;
+        let mut results: Vec<Option<R>> = (0..self.threads).map(|_| None).collect();
+        let mut panics = vec![];
+        for finished in 0..self.threads {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok((i, Ok(value))) => results[i] = Some(value),
+                Ok((i, Err(payload))) => {
+                    panics.push(format!("thread {}: {}", i, message(&*payload)))
+                }
+                Err(_) => panic!(
+                    "stress test timed out after {:?} with {} of {} threads finished",
+                    self.timeout, finished, self.threads
+                ),
+            }
+        }
+        if !panics.is_empty() {
+            panic!(
+                "{} of {} threads panicked:\n{}",
+                panics.len(),
+                self.threads,
+                panics.join("\n")
+            );
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_stress_collects_results() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = Arc::clone(&counter);
+        let results = stress(4, 1000).run(move |ctx| {
+            for _ in 0..ctx.iterations {
+                counter_clone.fetch_add(1, Ordering::Relaxed);
+            }
+            ctx.thread
+        });
+        assert_eq!(results, vec![0, 1, 2, 3]);
+        assert_eq!(counter.load(Ordering::Relaxed), 4000);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 of 4 threads panicked")]
+    fn test_stress_aggregates_panics() {
+        stress(4, 1).run(|ctx| {
+            if ctx.thread % 2 == 0 {
+                panic!("boom");
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "timed out")]
+    fn test_stress_timeout() {
+        stress(2, 1).timeout(Duration::from_millis(50)).run(|ctx| {
+            if ctx.thread == 0 {
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+    }
+}
@@ -0,0 +1,48 @@
+//  The primitives that loom can model take their atomics, cells, locks and spin hints from here,
+//  so that building with `RUSTFLAGS="--cfg loom"` swaps std's versions for loom's. Loom's types
+//  cannot be built in a const context, which is what `const_fn!` is for.
+//
+//      RUSTFLAGS="--cfg loom" cargo test --lib --release loom
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    cell::UnsafeCell,
+    hint,
+    sync::{atomic, Condvar, Mutex},
+};
+#[cfg(not(loom))]
+pub(crate) use std::{
+    hint,
+    sync::{atomic, Condvar, Mutex},
+};
+
+/// std's `UnsafeCell` behind loom's closure-based API, so loom can check every access to it.
+#[cfg(not(loom))]
+pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self(std::cell::UnsafeCell::new(value))
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+/// Declares a function that is `const` except under loom.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+
+pub(crate) use const_fn;
@@ -0,0 +1,471 @@
+#![allow(dead_code)]
+
+//  A growable ring buffer over a raw allocation. Elements live in `len` slots starting at `head`,
+//  wrapping past the end of the buffer; growing copies them out in order, so the new buffer starts
+//  contiguous again.
+
+use std::{
+    alloc::{self, Layout},
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ops::{Index, IndexMut},
+    ptr::{self, NonNull},
+    slice,
+};
+
+const MIN_CAPACITY: usize = 4;
+
+pub struct SafeVecDeque<T> {
+    ptr: NonNull<T>,
+    cap: usize,
+    head: usize,
+    len: usize,
+    _owns: PhantomData<T>,
+}
+
+unsafe impl<T> Send for SafeVecDeque<T> where T: Send {}
+unsafe impl<T> Sync for SafeVecDeque<T> where T: Sync {}
+
+impl<T> Default for SafeVecDeque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SafeVecDeque<T> {
+    const IS_ZST: bool = mem::size_of::<T>() == 0;
+
+    /// Does not allocate until the first push.
+    pub fn new() -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            //  zero-sized values need no storage, so there is never a reason to grow
+            cap: if Self::IS_ZST { usize::MAX } else { 0 },
+            head: 0,
+            len: 0,
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut deque = Self::new();
+        if !Self::IS_ZST && capacity > 0 {
+            deque.ptr = Self::allocate(capacity);
+            deque.cap = capacity;
+        }
+        deque
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn push_back(&mut self, value: T) {
+        if self.len == self.cap {
+            self.grow();
+        }
+        unsafe { self.slot(self.len).write(value) };
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        if self.len == self.cap {
+            self.grow();
+        }
+        self.head = if self.head == 0 {
+            self.cap - 1
+        } else {
+            self.head - 1
+        };
+        unsafe { self.slot(0).write(value) };
+        self.len += 1;
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.slot(self.len).read() })
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = unsafe { self.slot(0).read() };
+        self.head = self.wrap(1);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(unsafe { &*self.slot(index) })
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(unsafe { &mut *self.slot(index) })
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|last| self.get(last))
+    }
+
+    /// The elements in order, as the part up to the end of the buffer and the part that wrapped
+    /// around to its start.
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        let (front, back) = self.split();
+        unsafe {
+            (
+                slice::from_raw_parts(self.slot(0), front),
+                slice::from_raw_parts(self.ptr.as_ptr(), back),
+            )
+        }
+    }
+
+    pub fn as_mut_slices(&mut self) -> (&mut [T], &mut [T]) {
+        let (front, back) = self.split();
+        unsafe {
+            (
+                slice::from_raw_parts_mut(self.slot(0), front),
+                slice::from_raw_parts_mut(self.ptr.as_ptr(), back),
+            )
+        }
+    }
+
+    /// Moves the elements so they no longer wrap around, and returns them as one slice.
+    pub fn make_contiguous(&mut self) -> &mut [T] {
+        if Self::IS_ZST {
+            self.head = 0;
+        } else if self.split().1 > 0 {
+            //  rotating the whole buffer, uninitialized slots included, puts head at the start
+            let buffer = unsafe {
+                slice::from_raw_parts_mut(self.ptr.as_ptr().cast::<MaybeUninit<T>>(), self.cap)
+            };
+            buffer.rotate_left(self.head);
+            self.head = 0;
+        }
+        self.as_mut_slices().0
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        let (front, back) = self.as_slices();
+        Iter {
+            front: front.iter(),
+            back: back.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        let (front, back) = self.as_mut_slices();
+        IterMut {
+            front: front.iter_mut(),
+            back: back.iter_mut(),
+        }
+    }
+
+    //  the index into the buffer of the element `offset` places after head, written so that it
+    //  cannot overflow even with the zero-sized capacity of usize::MAX
+    fn wrap(&self, offset: usize) -> usize {
+        if offset < self.cap - self.head {
+            self.head + offset
+        } else {
+            offset - (self.cap - self.head)
+        }
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        unsafe { self.ptr.as_ptr().add(self.wrap(index)) }
+    }
+
+    //  how many elements sit before the end of the buffer, and how many wrapped around
+    fn split(&self) -> (usize, usize) {
+        let front = self.len.min(self.cap - self.head);
+        (front, self.len - front)
+    }
+
+    fn grow(&mut self) {
+        assert!(!Self::IS_ZST, "capacity overflow");
+        let cap = self.cap.checked_mul(2).expect("capacity overflow");
+        let cap = cap.max(MIN_CAPACITY);
+        let ptr = Self::allocate(cap);
+        let (front, back) = self.split();
+        unsafe {
+            ptr::copy_nonoverlapping(self.slot(0), ptr.as_ptr(), front);
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr().add(front), back);
+            self.deallocate();
+        }
+        self.ptr = ptr;
+        self.cap = cap;
+        self.head = 0;
+    }
+
+    fn allocate(cap: usize) -> NonNull<T> {
+        let layout = Layout::array::<T>(cap).expect("capacity overflow");
+        let ptr = unsafe { alloc::alloc(layout) };
+        NonNull::new(ptr.cast()).unwrap_or_else(|| alloc::handle_alloc_error(layout))
+    }
+
+    //  frees the buffer without touching the elements in it
+    unsafe fn deallocate(&mut self) {
+        if !Self::IS_ZST && self.cap > 0 {
+            let layout = Layout::array::<T>(self.cap).unwrap();
+            alloc::dealloc(self.ptr.as_ptr().cast(), layout);
+        }
+    }
+}
+
+impl<T> Drop for SafeVecDeque<T> {
+    fn drop(&mut self) {
+        let (front, back) = self.as_mut_slices();
+        unsafe {
+            ptr::drop_in_place(front);
+            ptr::drop_in_place(back);
+            self.deallocate();
+        }
+    }
+}
+
+impl<T> Index<usize> for SafeVecDeque<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        let len = self.len;
+        self.get(index)
+            .unwrap_or_else(|| panic!("index {} out of bounds for length {}", index, len))
+    }
+}
+
+impl<T> IndexMut<usize> for SafeVecDeque<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len;
+        self.get_mut(index)
+            .unwrap_or_else(|| panic!("index {} out of bounds for length {}", index, len))
+    }
+}
+
+pub struct Iter<'a, T> {
+    front: slice::Iter<'a, T>,
+    back: slice::Iter<'a, T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.front.next().or_else(|| self.back.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.back.next_back().or_else(|| self.front.next_back())
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+pub struct IterMut<'a, T> {
+    front: slice::IterMut<'a, T>,
+    back: slice::IterMut<'a, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.front.next().or_else(|| self.back.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IterMut<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.back.next_back().or_else(|| self.front.next_back())
+    }
+}
+
+impl<T> ExactSizeIterator for IterMut<'_, T> {}
+
+/// Whatever is not consumed is dropped along with the deque it came from.
+pub struct IntoIter<T>(SafeVecDeque<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for SafeVecDeque<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SafeVecDeque<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut SafeVecDeque<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_both_ends() {
+        let mut deque = SafeVecDeque::new();
+        assert_eq!(deque.capacity(), 0);
+        assert_eq!(deque.pop_front(), None);
+        for i in 0..10 {
+            deque.push_back(i);
+            deque.push_front(-i);
+        }
+        assert_eq!(deque.len(), 20);
+        assert_eq!((deque.front(), deque.back()), (Some(&-9), Some(&9)));
+        assert_eq!(deque[10], 0);
+        deque[10] = 100;
+        assert_eq!(deque.get(20), None);
+        assert_eq!(deque.pop_front(), Some(-9));
+        assert_eq!(deque.pop_back(), Some(9));
+        let values: Vec<_> = deque.iter().copied().collect();
+        let expected: Vec<_> = (-8..=0).chain(100..=100).chain(1..=8).collect();
+        assert_eq!(values, expected);
+        assert_eq!(
+            deque.iter().rev().copied().collect::<Vec<_>>(),
+            expected.iter().rev().copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_wrap_and_grow() {
+        let mut deque = SafeVecDeque::with_capacity(4);
+        deque.push_back(1);
+        deque.push_back(2);
+        deque.pop_front();
+        deque.push_back(3);
+        deque.push_back(4);
+        deque.push_back(5);
+        //  the buffer is full and wraps around its end
+        assert_eq!(deque.capacity(), 4);
+        assert_eq!(deque.as_slices(), (&[2, 3, 4][..], &[5][..]));
+        deque.push_back(6);
+        assert_eq!(deque.capacity(), 8);
+        assert_eq!(deque.as_slices(), (&[2, 3, 4, 5, 6][..], &[][..]));
+    }
+
+    #[test]
+    fn test_make_contiguous() {
+        let mut deque = SafeVecDeque::with_capacity(8);
+        for i in 0..6 {
+            deque.push_back(i);
+        }
+        for _ in 0..4 {
+            deque.pop_front();
+        }
+        for i in 6..10 {
+            deque.push_back(i);
+        }
+        assert_eq!(deque.as_slices().1, &[8, 9]);
+        deque.make_contiguous().sort_by(|a, b| b.cmp(a));
+        assert_eq!(deque.as_slices(), (&[9, 8, 7, 6, 5, 4][..], &[][..]));
+        for value in &mut deque {
+            *value *= 2;
+        }
+        assert_eq!(
+            deque.into_iter().collect::<Vec<_>>(),
+            [18, 16, 14, 12, 10, 8]
+        );
+    }
+
+    #[test]
+    fn test_drops() {
+        let counted = Rc::new(());
+        let mut deque = SafeVecDeque::with_capacity(4);
+        for _ in 0..3 {
+            deque.push_back(Rc::clone(&counted));
+            deque.push_front(Rc::clone(&counted));
+        }
+        deque.pop_back();
+        assert_eq!(Rc::strong_count(&counted), 6);
+        let mut into_iter = deque.into_iter();
+        into_iter.next();
+        into_iter.next_back();
+        assert_eq!(Rc::strong_count(&counted), 4);
+        drop(into_iter);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let mut deque = SafeVecDeque::new();
+        for _ in 0..1_000_000 {
+            deque.push_front(());
+            deque.push_back(());
+        }
+        assert_eq!(deque.len(), 2_000_000);
+        assert_eq!(deque.capacity(), usize::MAX);
+        assert_eq!(deque.iter().count(), 2_000_000);
+        assert_eq!(deque.make_contiguous().len(), 2_000_000);
+        while deque.pop_front().is_some() {}
+        assert!(deque.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+
+//  A single-producer channel that only keeps the latest value. Receivers read it in place through
+//  a read lock, and a version counter bumped on every send tells each receiver whether it has
+//  seen the current value yet; receivers waiting for a change park on the counter's address.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize},
+    mpsc::{RecvError, SendError},
+    Arc,
+};
+
+use crate::{
+    parking,
+    rwlock::{RwReadGuard, RwSpinLock},
+};
+
+struct Shared<T> {
+    value: RwSpinLock<T>,
+    version: AtomicUsize,
+    closed: AtomicBool,
+    receivers: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn addr(&self) -> usize {
+        &self.version as *const _ as usize
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(std::sync::atomic::Ordering::Acquire)
+    }
+}
+
+/// Creates a watch channel holding `initial`, which receivers see as already seen.
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwSpinLock::new(initial),
+        version: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared, seen: 0 },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and wakes every receiver waiting in `changed`. Hands the value back if
+    /// there are no receivers left.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self
+            .shared
+            .receivers
+            .load(std::sync::atomic::Ordering::Acquire)
+            == 0
+        {
+            return Err(SendError(value));
+        }
+        self.send_modify(|current| *current = value);
+        Ok(())
+    }
+
+    /// Updates the value in place, whether or not anyone is receiving.
+    pub fn send_modify(&self, f: impl FnOnce(&mut T)) {
+        f(&mut self.shared.value.write());
+        self.shared
+            .version
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        parking::unpark_all(self.shared.addr());
+    }
+
+    pub fn borrow(&self) -> RwReadGuard<'_, T> {
+        self.shared.value.read()
+    }
+
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared
+            .receivers
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Receiver {
+            shared: Arc::clone(&self.shared),
+            seen: self
+                .shared
+                .version
+                .load(std::sync::atomic::Ordering::Acquire),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared
+            .closed
+            .store(true, std::sync::atomic::Ordering::Release);
+        parking::unpark_all(self.shared.addr());
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    seen: usize,
+}
+
+impl<T> Receiver<T> {
+    /// The latest value. Holding the guard blocks the sender, so it should not be kept for long.
+    pub fn borrow(&self) -> RwReadGuard<'_, T> {
+        self.shared.value.read()
+    }
+
+    /// Like `borrow`, but also marks the value as seen.
+    pub fn borrow_and_update(&mut self) -> RwReadGuard<'_, T> {
+        //  read the version first: if a send lands in between, the newer value is returned but
+        //  still counts as unseen, which costs a spurious wakeup rather than a missed one
+        self.seen = self
+            .shared
+            .version
+            .load(std::sync::atomic::Ordering::Acquire);
+        self.shared.value.read()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.shared
+            .version
+            .load(std::sync::atomic::Ordering::Acquire)
+            != self.seen
+    }
+
+    /// Blocks until a value this receiver has not seen is sent, and marks it as seen. Returns
+    /// `Err` if the sender is dropped first.
+    pub fn changed(&mut self) -> Result<(), RecvError> {
+        let shared = &*self.shared;
+        loop {
+            let version = shared.version.load(std::sync::atomic::Ordering::Acquire);
+            if version != self.seen {
+                self.seen = version;
+                return Ok(());
+            }
+            if shared.is_closed() {
+                return Err(RecvError);
+            }
+            parking::park(shared.addr(), || {
+                shared.version.load(std::sync::atomic::Ordering::Acquire) == version
+                    && !shared.is_closed()
+            });
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared
+            .receivers
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared
+            .receivers
+            .fetch_sub(1, std::sync::atomic::Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_borrow_latest() {
+        let (sender, mut receiver) = channel(0);
+        assert!(!receiver.has_changed());
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert!(receiver.has_changed());
+        assert_eq!(*receiver.borrow_and_update(), 2);
+        assert!(!receiver.has_changed());
+        drop(receiver);
+        assert_eq!(sender.send(3), Err(SendError(3)));
+        assert_eq!(*sender.subscribe().borrow(), 2);
+    }
+
+    #[test]
+    fn test_changed() {
+        let (sender, receiver) = channel(0);
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let mut receiver = receiver.clone();
+                thread::spawn(move || {
+                    let mut values = vec![];
+                    while receiver.changed().is_ok() {
+                        values.push(*receiver.borrow());
+                    }
+                    values
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(10));
+        sender.send(1).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        sender.send_modify(|value| *value += 1);
+        drop(sender);
+        //  updates may be coalesced, but every receiver ends on the last value
+        for handle in handles {
+            let values = handle.join().unwrap();
+            assert_eq!(values.last(), Some(&2));
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use std::cell::Cell;
+
+use atomics::mutex::AdaptiveMutexGuard;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    //  a shared guard would let two threads mutate the same `Cell`
+    assert_sync::<AdaptiveMutexGuard<'static, Cell<i32>>>();
+}
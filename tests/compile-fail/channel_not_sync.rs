@@ -0,0 +1,9 @@
+use std::rc::Rc;
+
+use atomics::channel::Channel;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<Channel<Rc<i32>>>();
+}
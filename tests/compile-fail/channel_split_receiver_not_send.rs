@@ -0,0 +1,7 @@
+use atomics::channel_split::Receiver;
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<Receiver<'static, i32>>();
+}
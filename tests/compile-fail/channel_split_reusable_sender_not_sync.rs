@@ -0,0 +1,7 @@
+use atomics::channel_split::ReusableSender;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<ReusableSender<'static, i32>>();
+}
@@ -0,0 +1,10 @@
+use std::cell::Cell;
+
+use atomics::mutex::MutexGuard;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    //  a shared guard would let two threads mutate the same `Cell`
+    assert_sync::<MutexGuard<'static, Cell<i32>>>();
+}
@@ -0,0 +1,10 @@
+use std::rc::Rc;
+
+use atomics::mutex::{Mutex, TicketLock};
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<Mutex<Rc<i32>>>();
+    assert_sync::<TicketLock<Rc<i32>>>();
+}
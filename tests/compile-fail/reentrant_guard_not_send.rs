@@ -0,0 +1,7 @@
+use atomics::mutex::ReentrantLockGuard;
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<ReentrantLockGuard<'static, i32>>();
+}
@@ -0,0 +1,10 @@
+use std::cell::Cell;
+
+use atomics::rwlock::RwSpinLock;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    //  readers share `&T` across threads, so `T` must be Sync as well as Send
+    assert_sync::<RwSpinLock<Cell<i32>>>();
+}
@@ -0,0 +1,11 @@
+use std::{rc::Rc, thread};
+
+use atomics::mutex::SpinLock;
+
+fn main() {
+    let lock = SpinLock::new(Rc::new(0));
+    let guard = lock.lock().unwrap();
+    thread::scope(|s| {
+        s.spawn(move || drop(guard));
+    });
+}
@@ -0,0 +1,13 @@
+use std::{cell::Cell, thread};
+
+use atomics::mutex::SpinLock;
+
+fn main() {
+    let lock = SpinLock::new(Cell::new(0));
+    let guard = lock.lock().unwrap();
+    //  two threads mutating the same `Cell` through a shared guard would race
+    thread::scope(|s| {
+        s.spawn(|| guard.set(1));
+        s.spawn(|| guard.set(2));
+    });
+}
@@ -0,0 +1,9 @@
+use std::rc::Rc;
+
+use atomics::mutex::SpinLock;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<SpinLock<Rc<i32>>>();
+}